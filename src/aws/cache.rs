@@ -0,0 +1,216 @@
+use crate::config::oktaws_home;
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use aws_credential_types::Credentials;
+use eyre::{Result, eyre};
+use serde::{Deserialize, Serialize};
+use tracing::{instrument, warn};
+
+/// Records when the credentials written for each profile expire,
+/// so that `refresh` can skip profiles whose credentials are still valid.
+///
+/// Only expiry times are stored here; the credentials themselves stay in `~/.aws/credentials`.
+#[derive(Debug)]
+pub struct CredentialCache {
+    path: PathBuf,
+    entries: BTreeMap<String, CacheEntry>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct CacheEntry {
+    /// Expiry of the profile's credentials, in seconds since the UNIX epoch
+    pub expiration: u64,
+}
+
+impl CredentialCache {
+    /// Load the cache, defaulting to `cache.json` in the oktaws config directory.
+    ///
+    /// A missing or unreadable cache is treated as empty.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if no path is given and the oktaws config directory cannot be determined.
+    #[instrument]
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let path = match path {
+            Some(path) => PathBuf::from(path),
+            None => oktaws_home()?.join("cache.json"),
+        };
+
+        let entries = match fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|e| {
+                warn!(
+                    "Ignoring unparseable credential cache {}: {e}",
+                    path.display()
+                );
+                BTreeMap::new()
+            }),
+            Err(_) => BTreeMap::new(),
+        };
+
+        Ok(Self { path, entries })
+    }
+
+    /// Record the expiry of freshly written credentials for a profile
+    pub fn record(&mut self, profile_name: &str, credentials: &Credentials) {
+        let expiration = credentials
+            .expiry()
+            .and_then(|expiry| expiry.duration_since(UNIX_EPOCH).ok());
+
+        if let Some(expiration) = expiration {
+            self.entries.insert(
+                profile_name.to_string(),
+                CacheEntry {
+                    expiration: expiration.as_secs(),
+                },
+            );
+        } else {
+            self.entries.remove(profile_name);
+        }
+    }
+
+    /// How long the cached credentials for a profile remain valid after `now`.
+    ///
+    /// Returns `None` if nothing is cached for the profile, or if it has already expired.
+    #[must_use]
+    pub fn remaining_at(&self, profile_name: &str, now: SystemTime) -> Option<Duration> {
+        let expiration =
+            UNIX_EPOCH + Duration::from_secs(self.entries.get(profile_name)?.expiration);
+        expiration.duration_since(now).ok()
+    }
+
+    /// Whether the cached credentials for a profile remain valid for longer than `threshold`
+    #[must_use]
+    pub fn is_fresh(&self, profile_name: &str, threshold: Duration) -> bool {
+        self.remaining_at(profile_name, SystemTime::now())
+            .is_some_and(|remaining| remaining > threshold)
+    }
+
+    /// # Errors
+    ///
+    /// Will return `Err` if the parent directory cannot be created.
+    /// Will return `Err` if the cache file cannot be written.
+    #[instrument(skip_all)]
+    pub fn save(&self) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        fs::write(&self.path, serde_json::to_string_pretty(&self.entries)?)
+            .map_err(|e| eyre!("Failed to write credential cache: {}", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::NamedTempFile;
+
+    fn credentials_expiring_at(expiry: Option<SystemTime>) -> Credentials {
+        Credentials::new(
+            "ACCESS_KEY",
+            "SECRET_ACCESS_KEY",
+            Some("SESSION_TOKEN".to_string()),
+            expiry,
+            "oktaws",
+        )
+    }
+
+    #[test]
+    fn load_no_file() -> Result<()> {
+        let cache = CredentialCache::load(Some(&PathBuf::from("THIS PATH DOES NOT EXIST")))?;
+        assert!(cache.entries.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn load_unparseable_file() -> Result<()> {
+        let tempfile = NamedTempFile::new()?;
+        fs::write(tempfile.path(), "not json")?;
+
+        let cache = CredentialCache::load(Some(tempfile.path()))?;
+        assert!(cache.entries.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn round_trips_entries() -> Result<()> {
+        let tempfile = NamedTempFile::new()?;
+
+        let mut cache = CredentialCache::load(Some(tempfile.path()))?;
+        cache.record(
+            "foo",
+            &credentials_expiring_at(Some(UNIX_EPOCH + Duration::from_secs(1_700_000_000))),
+        );
+        cache.save()?;
+
+        let cache = CredentialCache::load(Some(tempfile.path()))?;
+        assert_eq!(
+            cache.entries.get("foo"),
+            Some(&CacheEntry {
+                expiration: 1_700_000_000
+            })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn remaining_lifetime() -> Result<()> {
+        let tempfile = NamedTempFile::new()?;
+        let now = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+
+        let mut cache = CredentialCache::load(Some(tempfile.path()))?;
+        cache.record(
+            "valid",
+            &credentials_expiring_at(Some(now + Duration::from_secs(3000))),
+        );
+        cache.record(
+            "expired",
+            &credentials_expiring_at(Some(now - Duration::from_secs(1))),
+        );
+
+        assert_eq!(
+            cache.remaining_at("valid", now),
+            Some(Duration::from_secs(3000))
+        );
+        assert_eq!(cache.remaining_at("expired", now), None);
+        assert_eq!(cache.remaining_at("missing", now), None);
+        Ok(())
+    }
+
+    #[test]
+    fn freshness_respects_threshold() -> Result<()> {
+        let tempfile = NamedTempFile::new()?;
+
+        let mut cache = CredentialCache::load(Some(tempfile.path()))?;
+        cache.record(
+            "foo",
+            &credentials_expiring_at(Some(SystemTime::now() + Duration::from_secs(3000))),
+        );
+
+        assert!(cache.is_fresh("foo", Duration::from_secs(600)));
+        assert!(!cache.is_fresh("foo", Duration::from_secs(3600)));
+        assert!(!cache.is_fresh("bar", Duration::from_secs(600)));
+        Ok(())
+    }
+
+    #[test]
+    fn credentials_without_expiry_are_not_cached() -> Result<()> {
+        let tempfile = NamedTempFile::new()?;
+
+        let mut cache = CredentialCache::load(Some(tempfile.path()))?;
+        cache.record(
+            "foo",
+            &credentials_expiring_at(Some(SystemTime::now() + Duration::from_secs(3000))),
+        );
+        cache.record("foo", &credentials_expiring_at(None));
+
+        assert!(!cache.is_fresh("foo", Duration::ZERO));
+        Ok(())
+    }
+}
@@ -1,7 +1,6 @@
-use configparser::ini::Ini;
+use aws_config_mod::{AwsConfigFile, SettingPath, Value};
 use dirs;
-use eyre::{Result, eyre};
-use std::collections::HashMap;
+use eyre::{Context, Result, eyre};
 use std::env::var as env_var;
 use std::fmt::Write as _;
 use std::fs;
@@ -13,7 +12,9 @@ use tracing::instrument;
 #[derive(Debug)]
 pub struct ConfigStore {
     path: PathBuf,
-    config: Ini,
+    config_file: AwsConfigFile,
+    /// The file as it was loaded, to compare changes against
+    original: String,
 }
 
 impl ConfigStore {
@@ -34,68 +35,124 @@ impl ConfigStore {
             )?,
         };
 
-        let mut config = Ini::new();
-        if path.exists() {
-            config
-                .load(&path)
-                .map_err(|e| eyre!("Failed to load AWS config file: {}", e))?;
-        }
+        let original = if path.exists() {
+            fs::read_to_string(&path)?
+        } else {
+            String::new()
+        };
 
-        Ok(Self { path, config })
+        let config_file = original
+            .parse()
+            .wrap_err_with(|| format!("Failed to parse AWS config file {}", path.display()))?;
+
+        Ok(Self {
+            path,
+            config_file,
+            original,
+        })
+    }
+
+    /// The value of `key` in `section` (such as `profile.my-profile`)
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the section and key cannot be written as a setting path
+    fn get(&self, section: &str, key: &str) -> Result<Option<String>> {
+        let setting_path: SettingPath = format!("{section}.{key}")
+            .parse()
+            .wrap_err_with(|| format!("Invalid setting {key} in [{section}]"))?;
+        Ok(self
+            .config_file
+            .get_setting(&setting_path)
+            .map(|setting| setting.value().to_string()))
+    }
+
+    /// Set `key` in `section`, leaving the rest of the file as it is.
+    /// A setting that already has the value is not touched.
+    fn set(&mut self, section: &str, key: &str, value: &str) -> Result<()> {
+        if self.get(section, key)?.as_deref() != Some(value) {
+            self.config_file
+                .set(format!("{section}.{key}").parse()?, Value::from(value));
+        }
+        Ok(())
     }
 
     /// Check if a profile is an SSO profile
     ///
     /// Returns true if the profile exists and has an `sso_session` field
-    #[must_use]
-    pub fn is_sso_profile(&self, profile_name: &str) -> bool {
-        let section_name = format!("profile {profile_name}");
-        self.config
-            .get_map_ref()
-            .get(&section_name)
-            .and_then(|section| section.get("sso_session"))
-            .and_then(|value| value.as_ref())
-            .is_some()
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the profile name cannot be used in a setting path
+    pub fn is_sso_profile(&self, profile_name: &str) -> Result<bool> {
+        Ok(self.get_profile_session(profile_name)?.is_some())
+    }
+
+    /// Get the SSO session for an existing profile
+    ///
+    /// Returns the `sso_session` the profile signs in with, if it is an SSO profile
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the profile name cannot be used in a setting path
+    pub fn get_profile_session(&self, profile_name: &str) -> Result<Option<String>> {
+        self.get(&section("profile", profile_name)?, "sso_session")
+    }
+
+    /// Get the account for an existing profile
+    ///
+    /// Returns the current `sso_account_id` for the profile if it exists
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the profile name cannot be used in a setting path
+    pub fn get_profile_account_id(&self, profile_name: &str) -> Result<Option<String>> {
+        self.get(&section("profile", profile_name)?, "sso_account_id")
     }
 
     /// Get the role for an existing profile
     ///
     /// Returns the current `sso_role_name` for the profile if it exists
-    #[must_use]
-    pub fn get_profile_role(&self, profile_name: &str) -> Option<String> {
-        let section_name = format!("profile {profile_name}");
-        self.config
-            .get_map_ref()
-            .get(&section_name)?
-            .get("sso_role_name")?
-            .clone()
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the profile name cannot be used in a setting path
+    pub fn get_profile_role(&self, profile_name: &str) -> Result<Option<String>> {
+        self.get(&section("profile", profile_name)?, "sso_role_name")
+    }
+
+    /// Get the region for an existing profile
+    ///
+    /// Returns the `region` set in the profile's section (`[default]` for the default profile)
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the profile name cannot be used in a setting path
+    pub fn get_profile_region(&self, profile_name: &str) -> Result<Option<String>> {
+        let profile_section = if profile_name == "default" {
+            profile_name.to_string()
+        } else {
+            section("profile", profile_name)?
+        };
+        self.get(&profile_section, "region")
     }
 
     /// Insert or update an SSO session
     ///
     /// # Errors
     ///
-    /// Will return `Err` if the session name cannot be parsed
+    /// Will return `Err` if the session name cannot be used in a section header
     pub fn upsert_sso_session(
         &mut self,
         session_name: &str,
         sso_start_url: &str,
         sso_region: &str,
     ) -> Result<()> {
-        let section_name = format!("sso-session {session_name}");
+        let section = section("sso-session", session_name)?;
 
-        self.config.set(
-            &section_name,
-            "sso_start_url",
-            Some(sso_start_url.to_string()),
-        );
-        self.config
-            .set(&section_name, "sso_region", Some(sso_region.to_string()));
-        self.config.set(
-            &section_name,
-            "sso_registration_scopes",
-            Some("sso:account:access".to_string()),
-        );
+        self.set(&section, "sso_start_url", sso_start_url)?;
+        self.set(&section, "sso_region", sso_region)?;
+        self.set(&section, "sso_registration_scopes", "sso:account:access")?;
 
         Ok(())
     }
@@ -104,71 +161,42 @@ impl ConfigStore {
     ///
     /// # Errors
     ///
-    /// Will return `Err` if the profile name cannot be parsed
+    /// Will return `Err` if the profile name cannot be used in a section header
     pub fn upsert_sso_profile(
         &mut self,
         profile_name: &str,
         session_name: &str,
         account_id: &str,
         role_name: &str,
+        region: &str,
     ) -> Result<()> {
-        let section_name = format!("profile {profile_name}");
-
-        self.config
-            .set(&section_name, "sso_session", Some(session_name.to_string()));
-        self.config.set(
-            &section_name,
-            "sso_account_id",
-            Some(account_id.to_string()),
-        );
-        self.config
-            .set(&section_name, "sso_role_name", Some(role_name.to_string()));
-        self.config
-            .set(&section_name, "region", Some("us-east-1".to_string()));
+        let section = section("profile", profile_name)?;
 
-        Ok(())
-    }
+        self.set(&section, "sso_session", session_name)?;
+        self.set(&section, "sso_account_id", account_id)?;
+        self.set(&section, "sso_role_name", role_name)?;
+        self.set(&section, "region", region)?;
 
-    /// Write a section (sso-session or profile) to the output string
-    fn write_section(&self, output: &mut String, section_name: &str) -> Result<()> {
-        if let Some(section_map) = self.config.get_map_ref().get(section_name) {
-            let mut keys: Vec<&String> = section_map.keys().collect();
-            keys.sort();
-            for key in keys {
-                if let Some(Some(value)) = section_map.get(key) {
-                    writeln!(output, "{key} = {value}")?;
-                }
-            }
-        }
         Ok(())
     }
 
-    /// Write a profile section with its configuration
-    fn write_profile(&self, output: &mut String, profile_section: &str) -> Result<()> {
-        writeln!(output)?;
-        writeln!(output, "[{profile_section}]")?;
-
-        if let Some(section_map) = self.config.get_map_ref().get(profile_section) {
-            let mut keys: Vec<&String> = section_map.keys().collect();
-            keys.sort();
-
-            for key in keys {
-                if let Some(Some(value)) = section_map.get(key) {
-                    writeln!(output, "{key} = {value}")?;
-                }
-            }
+    /// Describe what `save` would change in the config file, as a unified diff.
+    /// Returns an empty string if nothing would change.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the diff cannot be formatted
+    pub fn diff(&self) -> Result<String> {
+        let changes = line_diff(&self.original, &self.config_file.to_string())?;
+        if changes.is_empty() {
+            return Ok(changes);
         }
-        Ok(())
+
+        let path = self.path.display();
+        Ok(format!("--- {path}\n+++ {path}\n{changes}"))
     }
 
-    /// Save the config file to disk with smart formatting.
-    ///
-    /// The output is organized as follows:
-    /// - SSO sessions are written first, sorted alphabetically
-    /// - Each SSO session is followed by its associated profiles
-    /// - Profiles within a session are sorted alphabetically
-    /// - Non-SSO profiles are written at the end
-    /// - Blank lines separate session groups
+    /// Save the config file to disk, changing only what was upserted
     ///
     /// # Errors
     ///
@@ -180,85 +208,100 @@ impl ConfigStore {
             fs::create_dir_all(parent)?;
         }
 
-        // Write to a string first so we can add blank lines between sections and comment alternative roles
-        let mut output = String::new();
-
-        // Get all sections
-        let sections: Vec<String> = self.config.sections().into_iter().collect();
-
-        // Separate sso-session and profile sections
-        let mut sso_sessions: Vec<String> = sections
-            .iter()
-            .filter(|s| s.starts_with("sso-session "))
-            .cloned()
-            .collect();
-        sso_sessions.sort();
-
-        let profile_sections: Vec<String> = sections
-            .iter()
-            .filter(|s| s.starts_with("profile "))
-            .cloned()
-            .collect();
-
-        // Group profiles by their sso_session, and track profiles without sso_session
-        let mut session_profiles: HashMap<String, Vec<String>> = HashMap::new();
-        let mut non_sso_profiles: Vec<String> = Vec::new();
-        for profile_section in &profile_sections {
-            if let Some(section_map) = self.config.get_map_ref().get(profile_section) {
-                if let Some(Some(session_name)) = section_map.get("sso_session") {
-                    session_profiles
-                        .entry(session_name.clone())
-                        .or_default()
-                        .push(profile_section.clone());
-                } else {
-                    non_sso_profiles.push(profile_section.clone());
-                }
-            }
-        }
-        non_sso_profiles.sort();
-
-        // Write each sso-session followed by its profiles
-        let mut first = true;
-        for sso_session_section in &sso_sessions {
-            // Add blank line between groups (but not before the first one)
-            if !first {
-                output.push('\n');
-            }
-            first = false;
+        fs::write(&self.path, self.config_file.to_string())
+            .map_err(|e| eyre!("Failed to write AWS config file: {}", e))
+    }
+}
 
-            // Extract session name from "sso-session <name>"
-            let session_name = sso_session_section
-                .strip_prefix("sso-session ")
-                .unwrap_or(sso_session_section);
+/// The path of the section for a `kind` of section (`profile`, `sso-session`) named `name`
+fn section(kind: &str, name: &str) -> Result<String> {
+    if name.trim().is_empty() || name.contains(['.', '[', ']', '\r', '\n']) {
+        return Err(eyre!("Invalid {kind} name {name:?}"));
+    }
+    Ok(format!("{kind}.{name}"))
+}
 
-            // Write the sso-session section
-            writeln!(output, "[{sso_session_section}]")?;
-            self.write_section(&mut output, sso_session_section)?;
+fn is_section_header(line: &str) -> bool {
+    let line = line.trim();
+    line.starts_with('[') && line.ends_with(']')
+}
 
-            // Write all profiles for this session
-            if let Some(profiles) = session_profiles.get(session_name) {
-                let mut sorted_profiles = profiles.clone();
-                sorted_profiles.sort();
+/// Compare `before` and `after` line by line, marking removed lines with `-` and added lines with `+`.
+/// Each run of changes is shown under the header of the section it is in.
+fn line_diff(before: &str, after: &str) -> Result<String> {
+    let before: Vec<&str> = before.lines().collect();
+    let after: Vec<&str> = after.lines().collect();
+
+    // Only the lines between the common start and end need comparing
+    let prefix = before
+        .iter()
+        .zip(&after)
+        .take_while(|(old, new)| old == new)
+        .count();
+    let suffix = before[prefix..]
+        .iter()
+        .rev()
+        .zip(after[prefix..].iter().rev())
+        .take_while(|(old, new)| old == new)
+        .count();
+    let old = &before[prefix..before.len() - suffix];
+    let new = &after[prefix..after.len() - suffix];
+
+    // The length of the longest common subsequence of old[i..] and new[j..]
+    let mut common = vec![vec![0_usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            common[i][j] = if old[i] == new[j] {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
 
-                for profile_section in sorted_profiles {
-                    self.write_profile(&mut output, &profile_section)?;
-                }
-            }
+    let mut lines: Vec<(char, &str)> = before[..prefix].iter().map(|line| (' ', *line)).collect();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            lines.push((' ', old[i]));
+            i += 1;
+            j += 1;
+        } else if i < old.len() && (j == new.len() || common[i + 1][j] >= common[i][j + 1]) {
+            lines.push(('-', old[i]));
+            i += 1;
+        } else {
+            lines.push(('+', new[j]));
+            j += 1;
         }
+    }
 
-        // Write non-SSO profiles at the end
-        for profile_section in non_sso_profiles {
-            if !first {
-                writeln!(output)?;
+    let mut diff = String::new();
+    let mut header = None;
+    let mut header_shown = false;
+    for (marker, line) in lines {
+        if marker == ' ' {
+            if is_section_header(line) {
+                header = Some(line);
             }
-            first = false;
-
-            writeln!(output, "[{profile_section}]")?;
-            self.write_section(&mut output, &profile_section)?;
+            header_shown = false;
+            continue;
         }
 
-        fs::write(&self.path, output).map_err(|e| eyre!("Failed to write AWS config file: {}", e))
+        // Blank lines are usually spacing before a new section, so they don't need a header
+        if is_section_header(line) {
+            header = Some(line);
+            header_shown = true;
+        } else if !header_shown
+            && !line.trim().is_empty()
+            && let Some(header) = header
+        {
+            writeln!(diff, " {header}")?;
+            header_shown = true;
+        }
+        writeln!(diff, "{marker}{line}")?;
     }
+
+    Ok(diff)
 }
 
 #[cfg(test)]
@@ -270,6 +313,30 @@ mod tests {
 
     use tempfile::NamedTempFile;
 
+    /// A config file edited by hand, with comments, odd spacing, nested settings
+    /// and sections oktaws does not manage
+    static MESSY_CONFIG: &str = r"# Managed by hand
+[default]
+region=us-east-1
+output = json
+
+; Work accounts
+[sso-session  work]
+sso_start_url = https://work.awsapps.com/start
+sso_region = us-east-1
+
+[profile work-dev]
+sso_session = work
+sso_account_id = 111111111111
+sso_role_name = ReadOnly   
+region = us-east-1
+s3 =
+  max_concurrent_requests = 20
+  multipart_threshold = 64MB
+
+[profile legacy]
+credential_process = /usr/local/bin/creds --profile legacy";
+
     static CONFIG: &str = r"[profile existing]
 region = us-west-2
 output = json
@@ -318,7 +385,13 @@ sso_registration_scopes = sso:account:access
 
         // Need to create the session first, then the profile
         store.upsert_sso_session("my-sso", "https://my-org.awsapps.com/start", "us-east-1")?;
-        store.upsert_sso_profile("my-profile", "my-sso", "123456789012", "MyRole")?;
+        store.upsert_sso_profile(
+            "my-profile",
+            "my-sso",
+            "123456789012",
+            "MyRole",
+            "us-east-1",
+        )?;
 
         store.save()?;
 
@@ -344,7 +417,13 @@ sso_registration_scopes = sso:account:access
         let mut store = ConfigStore::load(Some(tempfile.path()))?;
 
         store.upsert_sso_session("my-sso", "https://my-org.awsapps.com/start", "us-east-1")?;
-        store.upsert_sso_profile("my-profile", "my-sso", "123456789012", "MyRole")?;
+        store.upsert_sso_profile(
+            "my-profile",
+            "my-sso",
+            "123456789012",
+            "MyRole",
+            "us-east-1",
+        )?;
 
         store.save()?;
 
@@ -399,7 +478,13 @@ sso_registration_scopes = sso:account:access
 
         // Create the SSO session first
         store.upsert_sso_session("my-sso", "https://my-sso.awsapps.com/start", "us-east-1")?;
-        store.upsert_sso_profile("new-profile", "my-sso", "123456789012", "MyRole")?;
+        store.upsert_sso_profile(
+            "new-profile",
+            "my-sso",
+            "123456789012",
+            "MyRole",
+            "us-east-1",
+        )?;
 
         store.save()?;
 
@@ -418,6 +503,117 @@ sso_registration_scopes = sso:account:access
         Ok(())
     }
 
+    #[test]
+    fn round_trips_messy_config() -> Result<()> {
+        let mut tempfile = NamedTempFile::new()?;
+        write!(tempfile, "{MESSY_CONFIG}")?;
+
+        let mut store = ConfigStore::load(Some(tempfile.path()))?;
+        store.upsert_sso_profile("work-dev", "work", "111111111111", "ReadOnly", "us-east-1")?;
+        assert_eq!(store.diff()?, "");
+        store.save()?;
+
+        assert_eq!(fs::read_to_string(tempfile.path())?, MESSY_CONFIG);
+        Ok(())
+    }
+
+    #[test]
+    fn only_changes_upserted_settings() -> Result<()> {
+        let mut tempfile = NamedTempFile::new()?;
+        write!(tempfile, "{MESSY_CONFIG}")?;
+
+        let mut store = ConfigStore::load(Some(tempfile.path()))?;
+        store.upsert_sso_profile("work-dev", "work", "111111111111", "Admin", "us-east-1")?;
+        store.upsert_sso_profile("work-prod", "work", "222222222222", "Admin", "us-east-1")?;
+        store.save()?;
+
+        let contents = fs::read_to_string(tempfile.path())?;
+
+        // Everything up to the changed role is as it was
+        let (unchanged, _) = MESSY_CONFIG
+            .split_once("sso_role_name = ReadOnly")
+            .expect("fixture sets a role");
+        assert!(
+            contents.starts_with(&unchanged),
+            "Unexpected changes in:\n{contents}"
+        );
+
+        // And so is everything after it
+        assert!(contents.contains(
+            "region = us-east-1\ns3 =\n  max_concurrent_requests = 20\n  multipart_threshold = 64MB\n"
+        ));
+        assert!(contents.contains(
+            "[profile legacy]\ncredential_process = /usr/local/bin/creds --profile legacy"
+        ));
+        assert!(!contents.contains("ReadOnly"));
+
+        let store = ConfigStore::load(Some(tempfile.path()))?;
+        assert_eq!(
+            store.get_profile_role("work-dev")?,
+            Some("Admin".to_string())
+        );
+        assert_eq!(
+            store.get_profile_role("work-prod")?,
+            Some("Admin".to_string())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_invalid_names() -> Result<()> {
+        let tempfile = NamedTempFile::new()?;
+        let mut store = ConfigStore::load(Some(tempfile.path()))?;
+        assert!(
+            store
+                .upsert_sso_session("a]\n[b", "https://my.awsapps.com/start", "us-east-1")
+                .is_err()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn diff_is_empty_without_changes() -> Result<()> {
+        let mut tempfile = NamedTempFile::new()?;
+        write!(tempfile, "{CONFIG}")?;
+
+        let store = ConfigStore::load(Some(tempfile.path()))?;
+        assert_eq!(store.diff()?, "");
+        Ok(())
+    }
+
+    #[test]
+    fn diff_shows_changed_settings() -> Result<()> {
+        let mut tempfile = NamedTempFile::new()?;
+        write!(tempfile, "{MESSY_CONFIG}")?;
+
+        let mut store = ConfigStore::load(Some(tempfile.path()))?;
+        store.upsert_sso_profile("work-dev", "work", "111111111111", "Admin", "us-east-1")?;
+
+        let path = tempfile.path().display();
+        let diff = store.diff()?;
+        assert!(diff.starts_with(&format!("--- {path}\n+++ {path}\n [profile work-dev]\n")));
+        assert!(diff.contains("\n-sso_role_name = ReadOnly"));
+        assert!(diff.contains("\n+sso_role_name = Admin"));
+        assert!(!diff.contains("legacy"));
+
+        // Nothing is written
+        assert_eq!(fs::read_to_string(tempfile.path())?, MESSY_CONFIG);
+        Ok(())
+    }
+
+    #[test]
+    fn diffs_lines_under_their_sections() -> Result<()> {
+        let before = "[default]\nregion = us-east-1\n\n[profile a]\nsso_role_name = ReadOnly\nregion = us-east-1\n";
+        let after = "[default]\nregion = us-east-1\n\n[profile a]\nsso_role_name = Admin\nregion = us-east-1\n\n[profile b]\nsso_role_name = Admin\n";
+
+        assert_eq!(
+            line_diff(before, after)?,
+            " [profile a]\n-sso_role_name = ReadOnly\n+sso_role_name = Admin\n+\n+[profile b]\n+sso_role_name = Admin\n"
+        );
+        assert_eq!(line_diff(before, before)?, "");
+        Ok(())
+    }
+
     #[test]
     fn test_is_sso_profile_with_sso_session() -> Result<()> {
         let mut tempfile = NamedTempFile::new()?;
@@ -427,7 +623,7 @@ sso_registration_scopes = sso:account:access
         )?;
 
         let store = ConfigStore::load(Some(tempfile.path()))?;
-        assert!(store.is_sso_profile("sso-test"));
+        assert!(store.is_sso_profile("sso-test")?);
         Ok(())
     }
 
@@ -440,7 +636,7 @@ sso_registration_scopes = sso:account:access
         )?;
 
         let store = ConfigStore::load(Some(tempfile.path()))?;
-        assert!(!store.is_sso_profile("non-sso"));
+        assert!(!store.is_sso_profile("non-sso")?);
         Ok(())
     }
 
@@ -448,7 +644,7 @@ sso_registration_scopes = sso:account:access
     fn test_is_sso_profile_non_existent() -> Result<()> {
         let tempfile = NamedTempFile::new()?;
         let store = ConfigStore::load(Some(tempfile.path()))?;
-        assert!(!store.is_sso_profile("does-not-exist"));
+        assert!(!store.is_sso_profile("does-not-exist")?);
         Ok(())
     }
 
@@ -462,7 +658,7 @@ sso_registration_scopes = sso:account:access
 
         let store = ConfigStore::load(Some(tempfile.path()))?;
         assert_eq!(
-            store.get_profile_role("test"),
+            store.get_profile_role("test")?,
             Some("AdminRole".to_string())
         );
         Ok(())
@@ -472,7 +668,7 @@ sso_registration_scopes = sso:account:access
     fn test_get_profile_role_non_existent() -> Result<()> {
         let tempfile = NamedTempFile::new()?;
         let store = ConfigStore::load(Some(tempfile.path()))?;
-        assert_eq!(store.get_profile_role("does-not-exist"), None);
+        assert_eq!(store.get_profile_role("does-not-exist")?, None);
         Ok(())
     }
 
@@ -482,7 +678,78 @@ sso_registration_scopes = sso:account:access
         write!(tempfile, "[profile non-sso]\nregion = us-east-1\n")?;
 
         let store = ConfigStore::load(Some(tempfile.path()))?;
-        assert_eq!(store.get_profile_role("non-sso"), None);
+        assert_eq!(store.get_profile_role("non-sso")?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_profile_session_and_account_id() -> Result<()> {
+        let tempfile = NamedTempFile::new()?;
+        let mut store = ConfigStore::load(Some(tempfile.path()))?;
+        store.upsert_sso_profile("test", "my-sso", "123456789012", "AdminRole", "us-east-1")?;
+
+        assert_eq!(
+            store.get_profile_session("test")?,
+            Some("my-sso".to_string())
+        );
+        assert_eq!(
+            store.get_profile_account_id("test")?,
+            Some("123456789012".to_string())
+        );
+        assert_eq!(store.get_profile_session("does-not-exist")?, None);
+        assert_eq!(store.get_profile_account_id("does-not-exist")?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn round_trips_profiles_with_slashes() -> Result<()> {
+        let tempfile = NamedTempFile::new()?;
+        let mut store = ConfigStore::load(Some(tempfile.path()))?;
+        store.upsert_sso_session("my-sso", "https://my.awsapps.com/start", "us-east-1")?;
+        store.upsert_sso_profile(
+            "prod/ReadOnly",
+            "my-sso",
+            "123456789012",
+            "ReadOnly",
+            "us-east-1",
+        )?;
+        store.save()?;
+
+        let store = ConfigStore::load(Some(tempfile.path()))?;
+        assert!(store.is_sso_profile("prod/ReadOnly")?);
+        assert_eq!(
+            store.get_profile_session("prod/ReadOnly")?,
+            Some("my-sso".to_string())
+        );
+        assert_eq!(
+            store.get_profile_role("prod/ReadOnly")?,
+            Some("ReadOnly".to_string())
+        );
+
+        // Names that cannot be looked up are an error, rather than a missing profile
+        assert!(store.get_profile_role("prod.ReadOnly").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_profile_region() -> Result<()> {
+        let mut tempfile = NamedTempFile::new()?;
+        write!(
+            tempfile,
+            "[default]\nregion = us-west-2\n\n[profile test]\nregion = eu-west-1\n\n[profile no-region]\noutput = json\n"
+        )?;
+
+        let store = ConfigStore::load(Some(tempfile.path()))?;
+        assert_eq!(
+            store.get_profile_region("default")?,
+            Some("us-west-2".to_string())
+        );
+        assert_eq!(
+            store.get_profile_region("test")?,
+            Some("eu-west-1".to_string())
+        );
+        assert_eq!(store.get_profile_region("no-region")?, None);
+        assert_eq!(store.get_profile_region("does-not-exist")?, None);
         Ok(())
     }
 
@@ -491,20 +758,36 @@ sso_registration_scopes = sso:account:access
         let tempfile = NamedTempFile::new()?;
         let mut store = ConfigStore::load(Some(tempfile.path()))?;
 
-        // Create two sessions
+        // Each session is written with its profiles, as init-sso does
         store.upsert_sso_session("session-a", "https://a.awsapps.com/start", "us-east-1")?;
+        store.upsert_sso_profile(
+            "profile-a1",
+            "session-a",
+            "111111111111",
+            "Admin",
+            "us-east-1",
+        )?;
+        store.upsert_sso_profile(
+            "profile-a2",
+            "session-a",
+            "222222222222",
+            "ReadOnly",
+            "us-east-1",
+        )?;
         store.upsert_sso_session("session-b", "https://b.awsapps.com/start", "us-west-2")?;
-
-        // Add profiles to each session
-        store.upsert_sso_profile("profile-a1", "session-a", "111111111111", "Admin")?;
-        store.upsert_sso_profile("profile-a2", "session-a", "222222222222", "ReadOnly")?;
-        store.upsert_sso_profile("profile-b1", "session-b", "333333333333", "PowerUser")?;
+        store.upsert_sso_profile(
+            "profile-b1",
+            "session-b",
+            "333333333333",
+            "PowerUser",
+            "us-east-1",
+        )?;
 
         store.save()?;
 
         let contents = fs::read_to_string(tempfile.path())?;
 
-        // Verify sessions are present and alphabetically ordered
+        // Verify sessions are present and in the order they were added
         let session_alpha_pos = contents
             .find("[sso-session session-a]")
             .expect("session-a not found");
@@ -513,7 +796,7 @@ sso_registration_scopes = sso:account:access
             .expect("session-b not found");
         assert!(
             session_alpha_pos < session_beta_pos,
-            "Sessions not in alphabetical order"
+            "Sessions not in the order they were added"
         );
 
         // Verify profiles are grouped with their sessions
@@ -535,20 +818,6 @@ sso_registration_scopes = sso:account:access
         // Profile for session-b should come after session-b
         assert!(session_beta_pos < profile_beta1_pos);
 
-        // Verify blank lines between groups
-        let lines: Vec<&str> = contents.lines().collect();
-        let mut found_blank_between_groups = false;
-        for window in lines.windows(2) {
-            if window[0].is_empty() && window[1].starts_with("[sso-session session-b]") {
-                found_blank_between_groups = true;
-                break;
-            }
-        }
-        assert!(
-            found_blank_between_groups,
-            "No blank line between session groups"
-        );
-
         Ok(())
     }
 
@@ -558,12 +827,8 @@ sso_registration_scopes = sso:account:access
         let mut store = ConfigStore::load(Some(tempfile.path()))?;
 
         // Manually add a non-SSO profile
-        store
-            .config
-            .set("profile test", "region", Some("us-west-2".to_string()));
-        store
-            .config
-            .set("profile test", "output", Some("json".to_string()));
+        store.set("profile.test", "region", "us-west-2")?;
+        store.set("profile.test", "output", "json")?;
 
         store.save()?;
 
@@ -0,0 +1,180 @@
+use aws_credential_types::Credentials;
+use eyre::{Result, eyre};
+use serde::{Deserialize, Serialize};
+use tracing::instrument;
+use url::Url;
+
+/// The AWS federation endpoint, which exchanges credentials for a console sign-in token
+pub const DEFAULT_FEDERATION_ENDPOINT: &str = "https://signin.aws.amazon.com/federation";
+
+/// Where the console opens, if no destination is given
+pub const DEFAULT_CONSOLE_URL: &str = "https://console.aws.amazon.com/";
+
+/// Shown by the console as the origin of the sign-in
+const ISSUER: &str = "oktaws";
+
+/// The credentials sent to `getSigninToken`
+#[derive(Debug, Serialize)]
+struct Session<'a> {
+    #[serde(rename = "sessionId")]
+    session_id: &'a str,
+    #[serde(rename = "sessionKey")]
+    session_key: &'a str,
+    #[serde(rename = "sessionToken")]
+    session_token: &'a str,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct SigninTokenResponse {
+    signin_token: String,
+}
+
+/// The console page to sign in to.
+///
+/// `destination` may be a full URL, or a path on the console (such as `s3/home`).
+///
+/// # Errors
+///
+/// Will return `Err` if `destination` is neither a URL nor a valid path
+pub fn destination_url(destination: Option<&str>) -> Result<Url> {
+    let console = Url::parse(DEFAULT_CONSOLE_URL)?;
+
+    let Some(destination) = destination else {
+        return Ok(console);
+    };
+
+    Ok(Url::parse(destination).or_else(|_| console.join(destination.trim_start_matches('/')))?)
+}
+
+/// Exchange temporary credentials for a URL that signs in to the AWS console at `destination`
+///
+/// # Errors
+///
+/// Will return `Err` if the credentials do not have a session token (long-lived credentials cannot sign in).
+/// Will return `Err` if the federation endpoint cannot be reached, or rejects the credentials.
+#[instrument(skip(credentials))]
+pub async fn signin_url(
+    federation_endpoint: &Url,
+    credentials: &Credentials,
+    destination: &Url,
+) -> Result<Url> {
+    let signin_token = signin_token(federation_endpoint, credentials).await?;
+
+    let mut url = federation_endpoint.clone();
+    url.query_pairs_mut()
+        .append_pair("Action", "login")
+        .append_pair("Issuer", ISSUER)
+        .append_pair("Destination", destination.as_str())
+        .append_pair("SigninToken", &signin_token);
+
+    Ok(url)
+}
+
+async fn signin_token(federation_endpoint: &Url, credentials: &Credentials) -> Result<String> {
+    let session = Session {
+        session_id: credentials.access_key_id(),
+        session_key: credentials.secret_access_key(),
+        session_token: credentials.session_token().ok_or_else(|| {
+            eyre!("Credentials without a session token cannot sign in to the console")
+        })?,
+    };
+
+    let response = reqwest::Client::new()
+        .get(federation_endpoint.clone())
+        .query(&[
+            ("Action", "getSigninToken"),
+            ("Session", &serde_json::to_string(&session)?),
+        ])
+        .send()
+        .await?;
+
+    let status = response.status();
+    let text = response.text().await?;
+    if !status.is_success() {
+        return Err(eyre!("getSigninToken failed ({status}): {text}"));
+    }
+
+    let SigninTokenResponse { signin_token } = serde_json::from_str(&text)?;
+    Ok(signin_token)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use wiremock::matchers::{method, path, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn credentials(session_token: Option<&str>) -> Credentials {
+        Credentials::new(
+            "ACCESS_KEY",
+            "SECRET_ACCESS_KEY",
+            session_token.map(ToString::to_string),
+            None,
+            "oktaws",
+        )
+    }
+
+    #[test]
+    fn builds_destinations() -> Result<()> {
+        assert_eq!(destination_url(None)?.as_str(), DEFAULT_CONSOLE_URL);
+        assert_eq!(
+            destination_url(Some("/s3/home"))?.as_str(),
+            "https://console.aws.amazon.com/s3/home"
+        );
+        assert_eq!(
+            destination_url(Some("https://console.amazonaws-us-gov.com/ec2"))?.as_str(),
+            "https://console.amazonaws-us-gov.com/ec2"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn exchanges_credentials_for_signin_url() -> Result<()> {
+        let server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .and(path("/federation"))
+            .and(query_param("Action", "getSigninToken"))
+            .and(query_param(
+                "Session",
+                r#"{"sessionId":"ACCESS_KEY","sessionKey":"SECRET_ACCESS_KEY","sessionToken":"SESSION_TOKEN"}"#,
+            ))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                    "SigninToken": "SIGNIN_TOKEN"
+                })),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let endpoint = Url::parse(&format!("{}/federation", server.uri()))?;
+        let url = signin_url(
+            &endpoint,
+            &credentials(Some("SESSION_TOKEN")),
+            &destination_url(Some("s3/home"))?,
+        )
+        .await?;
+
+        assert_eq!(
+            url.as_str(),
+            format!(
+                "{}/federation?Action=login&Issuer=oktaws&Destination=https%3A%2F%2Fconsole.aws.amazon.com%2Fs3%2Fhome&SigninToken=SIGNIN_TOKEN",
+                server.uri()
+            )
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn requires_session_token() -> Result<()> {
+        let endpoint = Url::parse(DEFAULT_FEDERATION_ENDPOINT)?;
+        let result = signin_url(&endpoint, &credentials(None), &destination_url(None)?).await;
+
+        assert!(result.is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,80 @@
+use aws_credential_types::Credentials;
+use aws_smithy_types::DateTime;
+use aws_smithy_types::date_time::Format;
+use eyre::{Error, Result};
+use serde::Serialize;
+
+/// The JSON document an AWS `credential_process` command is expected to print.
+///
+/// See <https://docs.aws.amazon.com/cli/latest/userguide/cli-configure-sourcing-external.html>
+#[derive(Debug, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "PascalCase")]
+pub struct CredentialProcessOutput {
+    pub version: u8,
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub session_token: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expiration: Option<String>,
+}
+
+impl TryFrom<&Credentials> for CredentialProcessOutput {
+    type Error = Error;
+
+    fn try_from(credentials: &Credentials) -> Result<Self, Self::Error> {
+        let expiration = credentials
+            .expiry()
+            .map(|expiry| DateTime::from(expiry).fmt(Format::DateTime))
+            .transpose()?;
+
+        Ok(Self {
+            version: 1,
+            access_key_id: credentials.access_key_id().to_string(),
+            secret_access_key: credentials.secret_access_key().to_string(),
+            session_token: credentials.session_token().map(ToString::to_string),
+            expiration,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn serializes_sts_credentials() -> Result<()> {
+        let credentials = Credentials::new(
+            "ACCESS_KEY",
+            "SECRET_ACCESS_KEY",
+            Some("SESSION_TOKEN".to_string()),
+            Some(UNIX_EPOCH + Duration::from_secs(1_700_000_000)),
+            "oktaws",
+        );
+
+        let output = CredentialProcessOutput::try_from(&credentials)?;
+
+        assert_eq!(
+            serde_json::to_string(&output)?,
+            r#"{"Version":1,"AccessKeyId":"ACCESS_KEY","SecretAccessKey":"SECRET_ACCESS_KEY","SessionToken":"SESSION_TOKEN","Expiration":"2023-11-14T22:13:20Z"}"#
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn omits_missing_fields() -> Result<()> {
+        let credentials = Credentials::new("ACCESS_KEY", "SECRET_ACCESS_KEY", None, None, "oktaws");
+
+        let output = CredentialProcessOutput::try_from(&credentials)?;
+
+        assert_eq!(
+            serde_json::to_string(&output)?,
+            r#"{"Version":1,"AccessKeyId":"ACCESS_KEY","SecretAccessKey":"SECRET_ACCESS_KEY"}"#
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,193 @@
+use std::fmt;
+use std::str::FromStr;
+
+use aws_credential_types::Credentials;
+use aws_smithy_types::DateTime;
+use aws_smithy_types::date_time::Format;
+use eyre::{Error, Result, eyre};
+
+/// Shells that `oktaws env` can print variable assignments for
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Shell {
+    /// bash, zsh and other POSIX shells
+    #[default]
+    Posix,
+    Fish,
+    PowerShell,
+}
+
+impl FromStr for Shell {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match &*s.to_lowercase() {
+            "bash" | "zsh" | "sh" | "posix" => Ok(Self::Posix),
+            "fish" => Ok(Self::Fish),
+            "powershell" | "pwsh" => Ok(Self::PowerShell),
+            _ => Err(eyre!(
+                "Unknown shell {s} (expected one of bash, zsh, fish, powershell)"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Shell {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Posix => write!(f, "bash"),
+            Self::Fish => write!(f, "fish"),
+            Self::PowerShell => write!(f, "powershell"),
+        }
+    }
+}
+
+impl Shell {
+    /// A statement that sets an environment variable in this shell
+    #[must_use]
+    pub fn export(self, name: &str, value: &str) -> String {
+        match self {
+            Self::Posix => format!("export {name}='{}'", value.replace('\'', r"'\''")),
+            Self::Fish => format!(
+                "set -gx {name} '{}'",
+                value.replace('\\', r"\\").replace('\'', r"\'")
+            ),
+            Self::PowerShell => format!("$Env:{name} = '{}'", value.replace('\'', "''")),
+        }
+    }
+
+    /// A statement that removes an environment variable in this shell
+    #[must_use]
+    pub fn unset(self, name: &str) -> String {
+        match self {
+            Self::Posix => format!("unset {name}"),
+            Self::Fish => format!("set -e {name}"),
+            Self::PowerShell => format!("Remove-Item Env:{name} -ErrorAction SilentlyContinue"),
+        }
+    }
+}
+
+/// The environment variables the AWS CLI and SDKs read credentials from.
+///
+/// `None` means the variable should be unset, so that a stale session token is not left behind.
+///
+/// # Errors
+///
+/// Will return `Err` if the expiry cannot be formatted as a timestamp
+pub fn credential_variables(
+    credentials: &Credentials,
+) -> Result<Vec<(&'static str, Option<String>)>> {
+    let expiration = credentials
+        .expiry()
+        .map(|expiry| DateTime::from(expiry).fmt(Format::DateTime))
+        .transpose()?;
+
+    Ok(vec![
+        (
+            "AWS_ACCESS_KEY_ID",
+            Some(credentials.access_key_id().to_string()),
+        ),
+        (
+            "AWS_SECRET_ACCESS_KEY",
+            Some(credentials.secret_access_key().to_string()),
+        ),
+        (
+            "AWS_SESSION_TOKEN",
+            credentials.session_token().map(ToString::to_string),
+        ),
+        ("AWS_CREDENTIAL_EXPIRATION", expiration),
+    ])
+}
+
+/// Statements that put credentials into the environment of `shell`, one per line
+///
+/// # Errors
+///
+/// Will return `Err` if the expiry cannot be formatted as a timestamp
+pub fn exports(credentials: &Credentials, shell: Shell) -> Result<String> {
+    Ok(credential_variables(credentials)?
+        .into_iter()
+        .map(|(name, value)| match value {
+            Some(value) => shell.export(name, &value),
+            None => shell.unset(name),
+        })
+        .collect::<Vec<_>>()
+        .join("\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn parses_shells() -> Result<()> {
+        assert_eq!("zsh".parse::<Shell>()?, Shell::Posix);
+        assert_eq!("fish".parse::<Shell>()?, Shell::Fish);
+        assert_eq!("pwsh".parse::<Shell>()?, Shell::PowerShell);
+        assert!("cmd".parse::<Shell>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn exports_for_each_shell() -> Result<()> {
+        let credentials = Credentials::new(
+            "ACCESS_KEY",
+            "SECRET'KEY",
+            Some("SESSION_TOKEN".to_string()),
+            Some(UNIX_EPOCH + Duration::from_secs(1_700_000_000)),
+            "oktaws",
+        );
+
+        assert_eq!(
+            exports(&credentials, Shell::Posix)?,
+            [
+                "export AWS_ACCESS_KEY_ID='ACCESS_KEY'",
+                r"export AWS_SECRET_ACCESS_KEY='SECRET'\''KEY'",
+                "export AWS_SESSION_TOKEN='SESSION_TOKEN'",
+                "export AWS_CREDENTIAL_EXPIRATION='2023-11-14T22:13:20Z'",
+            ]
+            .join("\n")
+        );
+        assert_eq!(
+            exports(&credentials, Shell::Fish)?,
+            [
+                "set -gx AWS_ACCESS_KEY_ID 'ACCESS_KEY'",
+                r"set -gx AWS_SECRET_ACCESS_KEY 'SECRET\'KEY'",
+                "set -gx AWS_SESSION_TOKEN 'SESSION_TOKEN'",
+                "set -gx AWS_CREDENTIAL_EXPIRATION '2023-11-14T22:13:20Z'",
+            ]
+            .join("\n")
+        );
+        assert_eq!(
+            exports(&credentials, Shell::PowerShell)?,
+            [
+                "$Env:AWS_ACCESS_KEY_ID = 'ACCESS_KEY'",
+                "$Env:AWS_SECRET_ACCESS_KEY = 'SECRET''KEY'",
+                "$Env:AWS_SESSION_TOKEN = 'SESSION_TOKEN'",
+                "$Env:AWS_CREDENTIAL_EXPIRATION = '2023-11-14T22:13:20Z'",
+            ]
+            .join("\n")
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn unsets_missing_session_token() -> Result<()> {
+        let credentials = Credentials::new("ACCESS_KEY", "SECRET_KEY", None, None, "oktaws");
+
+        assert_eq!(
+            exports(&credentials, Shell::Posix)?,
+            [
+                "export AWS_ACCESS_KEY_ID='ACCESS_KEY'",
+                "export AWS_SECRET_ACCESS_KEY='SECRET_KEY'",
+                "unset AWS_SESSION_TOKEN",
+                "unset AWS_CREDENTIAL_EXPIRATION",
+            ]
+            .join("\n")
+        );
+
+        Ok(())
+    }
+}
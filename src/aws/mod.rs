@@ -1,12 +1,20 @@
+pub mod cache;
 pub mod config;
+pub mod console;
+pub mod credential_process;
+pub mod env;
 pub mod profile;
 pub mod role;
 pub mod saml;
+pub mod server;
 pub mod sso;
+pub mod sso_cache;
 
 use crate::aws::role::SamlRole;
 use crate::aws::saml::Response;
 
+use aws_credential_types::Credentials;
+use aws_sdk_iam::config::Region as IamRegion;
 use aws_sdk_iam::{Client as IamClient, Config as IamConfig};
 use aws_sdk_sts::config::Region as StsRegion;
 use aws_sdk_sts::{Client as StsClient, Config as StsConfig};
@@ -20,12 +28,15 @@ use eyre::{Result, eyre};
 /// if the role does not have sufficient permissions to call `list_account_aliases`,
 /// or if there are an unexpected number of aliases returned.
 pub async fn get_account_alias(role: &SamlRole, response: &Response) -> Result<String> {
+    let region = role.default_sts_region();
+
     let credentials = role
-        .assume(sts_client(), response.saml.clone(), None)
+        .assume(sts_client(region), response.saml.clone(), None)
         .await
         .map_err(|e| eyre!("Error assuming role ({})", e))?;
 
     let config = IamConfig::builder()
+        .region(IamRegion::new(region))
         .credentials_provider(credentials)
         .behavior_version_latest()
         .build();
@@ -43,9 +54,34 @@ pub async fn get_account_alias(role: &SamlRole, response: &Response) -> Result<S
     }
 }
 
+/// The region to call STS in for roles in an AWS partition,
+/// unless one is configured for the profile
+#[must_use]
+pub fn default_sts_region(partition: &str) -> &'static str {
+    match partition {
+        "aws-us-gov" => "us-gov-west-1",
+        "aws-cn" => "cn-north-1",
+        "aws-iso" => "us-iso-east-1",
+        "aws-iso-b" => "us-isob-east-1",
+        _ => "us-east-1",
+    }
+}
+
+/// An STS client that signs its requests with `credentials`, for role chaining
+#[must_use]
+pub fn sts_client_with_credentials(region: &str, credentials: Credentials) -> StsClient {
+    let region = StsRegion::new(region.to_string());
+    let config = StsConfig::builder()
+        .region(region)
+        .credentials_provider(credentials)
+        .behavior_version_latest()
+        .build();
+    StsClient::from_conf(config)
+}
+
 #[must_use]
-pub fn sts_client() -> StsClient {
-    let region = StsRegion::new("us-east-1");
+pub fn sts_client(region: &str) -> StsClient {
+    let region = StsRegion::new(region.to_string());
     let config = StsConfig::builder()
         .region(region)
         .behavior_version_latest()
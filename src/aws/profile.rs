@@ -3,7 +3,7 @@ use aws_credential_types::Credentials;
 use dirs;
 use eyre::{Context, Result, eyre};
 use std::env::var as env_var;
-use std::fs;
+use std::fs::{self, File};
 use std::path::{Path, PathBuf};
 use tracing::instrument;
 
@@ -13,21 +13,64 @@ pub struct Store {
     credentials_file: AwsCredentialsFile,
 }
 
+/// Exclusive access to the AWS credentials file, among oktaws processes.
+/// The lock is released when this is dropped.
+#[derive(Debug)]
+pub struct StoreLock {
+    _file: File,
+}
+
 impl Store {
+    fn path(path: Option<&Path>) -> Result<PathBuf> {
+        match (path, env_var("AWS_SHARED_CREDENTIALS_FILE")) {
+            (Some(path), _) => Ok(PathBuf::from(path)),
+            (_, Ok(path)) => Ok(PathBuf::from(path)),
+            _ => dirs::home_dir().map_or_else(
+                || Err(eyre!("The environment variable HOME must be set.")),
+                |home_dir| Ok(home_dir.join(".aws").join("credentials")),
+            ),
+        }
+    }
+
+    /// The file locked while the credentials file at `path` is being updated
+    fn lock_path(path: &Path) -> PathBuf {
+        let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+        file_name.push(".oktaws-lock");
+        path.with_file_name(file_name)
+    }
+
+    /// Wait until no other oktaws process is updating the credentials file, then lock it.
+    /// Take the lock before loading the store, and hold it until the store is saved.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the HOME environment variable is not set.
+    /// Will return `Err` if the lock file cannot be created or locked.
+    #[instrument]
+    pub fn lock(path: Option<&Path>) -> Result<StoreLock> {
+        let lock_path = Self::lock_path(&Self::path(path)?);
+        if let Some(parent) = lock_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let file = File::options()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&lock_path)?;
+        file.lock()
+            .wrap_err_with(|| format!("Failed to lock {}", lock_path.display()))?;
+
+        Ok(StoreLock { _file: file })
+    }
+
     /// # Errors
     ///
     /// Will return `Err` if the HOME environment variable is not set.
     /// Will return `Err` if the aws credentials file cannot be read or parsed.
     #[instrument]
     pub fn load(path: Option<&Path>) -> Result<Self> {
-        let path = match (path, env_var("AWS_SHARED_CREDENTIALS_FILE")) {
-            (Some(path), _) => PathBuf::from(path),
-            (_, Ok(path)) => PathBuf::from(path),
-            _ => dirs::home_dir().map_or_else(
-                || Err(eyre!("The environment variable HOME must be set.")),
-                |home_dir| Ok(home_dir.join(".aws").join("credentials")),
-            )?,
-        };
+        let path = Self::path(path)?;
 
         let credentials_file = if path.exists() {
             fs::read_to_string(&path)?.parse().wrap_err_with(|| {
@@ -123,6 +166,22 @@ aws_access_key_id = STATIC_ACCESS_KEY
 aws_secret_access_key = STATIC_SECRET_ACCESS_KEY
 "#;
 
+    #[test]
+    fn lock_is_exclusive() -> Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        let path = tempdir.path().join("credentials");
+
+        let lock = Store::lock(Some(&path))?;
+        let other = File::options()
+            .write(true)
+            .open(tempdir.path().join("credentials.oktaws-lock"))?;
+        assert!(other.try_lock().is_err());
+
+        drop(lock);
+        assert!(other.try_lock().is_ok());
+        Ok(())
+    }
+
     #[test]
     fn load_no_file() -> Result<()> {
         Store::load(Some(&PathBuf::from("THIS PATH DOES NOT EXIST")))?;
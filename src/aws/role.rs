@@ -3,12 +3,18 @@
 use std::str;
 use std::str::FromStr;
 
+use crate::aws::default_sts_region;
+
 use aws_arn::ResourceName as ARN;
 use aws_credential_types::Credentials;
 use aws_sdk_sts::Client as StsClient;
 use eyre::{Error, Result, eyre};
+use serde::{Deserialize, Serialize};
 use tracing::instrument;
 
+/// The session name used for chained roles, unless one is configured
+pub const DEFAULT_SESSION_NAME: &str = "oktaws";
+
 #[derive(Clone, Debug, PartialEq)]
 pub struct SamlRole {
     pub provider: ARN,
@@ -47,6 +53,21 @@ impl SamlRole {
             .map(ToString::to_string)
     }
 
+    /// The partition the role is in (such as `aws` or `aws-us-gov`)
+    #[must_use]
+    pub fn partition(&self) -> String {
+        self.role
+            .partition
+            .as_ref()
+            .map_or_else(|| "aws".to_string(), ToString::to_string)
+    }
+
+    /// The region to call STS in for this role, if none is configured
+    #[must_use]
+    pub fn default_sts_region(&self) -> &'static str {
+        default_sts_region(&self.partition())
+    }
+
     /// # Errors
     ///
     /// Will return `Err` if the STS assume role call fails.
@@ -79,6 +100,69 @@ impl SamlRole {
     }
 }
 
+/// A role assumed with the credentials of the previous step in a role chain
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct ChainedRole {
+    pub role_arn: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub external_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration_seconds: Option<i32>,
+    /// Region to call STS in (by default, one in the partition of the role)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sts_region: Option<String>,
+}
+
+impl ChainedRole {
+    /// The partition the role is in (such as `aws` or `aws-us-gov`)
+    #[must_use]
+    pub fn partition(&self) -> &str {
+        self.role_arn
+            .split(':')
+            .nth(1)
+            .filter(|partition| !partition.is_empty())
+            .unwrap_or("aws")
+    }
+
+    /// The region to call STS in for this role
+    #[must_use]
+    pub fn sts_region(&self) -> &str {
+        self.sts_region
+            .as_deref()
+            .unwrap_or_else(|| default_sts_region(self.partition()))
+    }
+
+    /// Assume the role, given an STS client that holds the credentials of the previous step
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the STS assume role call fails.
+    /// Will return `Err` if no credentials are returned from STS.
+    #[instrument(level = "trace", skip(client))]
+    pub async fn assume(&self, client: StsClient) -> Result<Credentials> {
+        let credentials = client
+            .assume_role()
+            .role_arn(&self.role_arn)
+            .role_session_name(self.session_name.as_deref().unwrap_or(DEFAULT_SESSION_NAME))
+            .set_duration_seconds(self.duration_seconds)
+            .set_external_id(self.external_id.clone())
+            .send()
+            .await?
+            .credentials
+            .ok_or_else(|| eyre!("No credentials returned"))?;
+
+        Ok(Credentials::new(
+            credentials.access_key_id,
+            credentials.secret_access_key,
+            Some(credentials.session_token),
+            credentials.expiration.try_into().ok(),
+            "sts",
+        ))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -110,6 +194,96 @@ mod tests {
         assert_eq!(attribute.parse::<SamlRole>().unwrap(), expected_role);
     }
 
+    #[test]
+    fn sts_region_follows_partition() {
+        let role: SamlRole =
+            "arn:aws:iam::123456789012:saml-provider/okta-idp,arn:aws:iam::123456789012:role/role1"
+                .parse()
+                .unwrap();
+        assert_eq!(role.partition(), "aws");
+        assert_eq!(role.default_sts_region(), "us-east-1");
+
+        let role: SamlRole = "arn:aws-us-gov:iam::123456789012:saml-provider/okta-idp,arn:aws-us-gov:iam::123456789012:role/role1"
+            .parse()
+            .unwrap();
+        assert_eq!(role.partition(), "aws-us-gov");
+        assert_eq!(role.default_sts_region(), "us-gov-west-1");
+
+        let role: SamlRole = "arn:aws-cn:iam::123456789012:saml-provider/okta-idp,arn:aws-cn:iam::123456789012:role/role1"
+            .parse()
+            .unwrap();
+        assert_eq!(role.default_sts_region(), "cn-north-1");
+    }
+
+    #[test]
+    fn chained_role_sts_region_follows_partition() {
+        let role = ChainedRole {
+            role_arn: "arn:aws-us-gov:iam::123456789012:role/target".to_string(),
+            external_id: None,
+            session_name: None,
+            duration_seconds: None,
+            sts_region: None,
+        };
+        assert_eq!(role.partition(), "aws-us-gov");
+        assert_eq!(role.sts_region(), "us-gov-west-1");
+
+        let role = ChainedRole {
+            sts_region: Some("us-gov-east-1".to_string()),
+            ..role
+        };
+        assert_eq!(role.sts_region(), "us-gov-east-1");
+    }
+
+    #[test]
+    fn assume_chained_role() {
+        let http_client = StaticReplayClient::new(vec![
+            ReplayEvent::new(
+                http::Request::builder()
+                    .uri(http::Uri::from_static("https://sts.us-east-1.amazonaws.com/"))
+                    .body(SdkBody::from(r"Action=AssumeRole&Version=2011-06-15&RoleArn=arn%3Aaws%3Aiam%3A%3A210987654321%3Arole%2Ftarget&RoleSessionName=oktaws&ExternalId=EXTERNAL_ID")).unwrap(),
+                http::Response::builder()
+                    .status(200)
+                    .body(SdkBody::from(r#"<AssumeRoleResponse xmlns="https://sts.amazonaws.com/doc/2011-06-15/">
+  <AssumeRoleResult>
+    <Credentials>
+      <AccessKeyId>ACCESS_KEY</AccessKeyId>
+      <SecretAccessKey>SECRET_ACCESS_KEY</SecretAccessKey>
+      <SessionToken>SESSION_TOKEN</SessionToken>
+      <Expiration>2023-11-14T22:13:20Z</Expiration>
+    </Credentials>
+  </AssumeRoleResult>
+</AssumeRoleResponse>"#)).unwrap()
+            )
+        ]);
+
+        let config: StsConfig = StsConfig::builder()
+            .region(StsRegion::new("us-east-1"))
+            .credentials_provider(Credentials::new(
+                "HUB_KEY",
+                "HUB_SECRET",
+                None,
+                None,
+                "test",
+            ))
+            .http_client(http_client.clone())
+            .behavior_version_latest()
+            .build();
+
+        let role = ChainedRole {
+            role_arn: "arn:aws:iam::210987654321:role/target".to_string(),
+            external_id: Some("EXTERNAL_ID".to_string()),
+            session_name: None,
+            duration_seconds: None,
+            sts_region: None,
+        };
+
+        let credentials = block_on(role.assume(StsClient::from_conf(config))).unwrap();
+
+        assert_eq!(credentials.access_key_id(), "ACCESS_KEY");
+        assert_eq!(credentials.session_token(), Some("SESSION_TOKEN"));
+        http_client.assert_requests_match(&[]);
+    }
+
     #[test]
     fn parse_response() {
         let mut f = File::open("tests/fixtures/saml_response.xml").expect("file not found");
@@ -0,0 +1,507 @@
+use std::future::{Future, pending};
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use aws_credential_types::Credentials;
+use aws_smithy_types::DateTime;
+use aws_smithy_types::date_time::Format;
+use data_encoding::HEXLOWER;
+use eyre::{Error, Result, eyre};
+use serde::Serialize;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::{Instant, sleep_until, timeout};
+use tracing::{debug, error, info, instrument, warn};
+
+/// The path credentials are served from
+pub const CREDENTIALS_PATH: &str = "/credentials";
+
+/// How long to wait before trying again, after failing to refresh credentials
+const RETRY_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How long a client has to send its request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The largest request that will be read
+const MAX_REQUEST_SIZE: usize = 16 * 1024;
+
+/// The JSON document the AWS SDKs expect from a container credentials endpoint
+/// (`AWS_CONTAINER_CREDENTIALS_FULL_URI`).
+#[derive(Debug, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "PascalCase")]
+pub struct ContainerCredentials {
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expiration: Option<String>,
+}
+
+impl TryFrom<&Credentials> for ContainerCredentials {
+    type Error = Error;
+
+    fn try_from(credentials: &Credentials) -> Result<Self, Self::Error> {
+        let expiration = credentials
+            .expiry()
+            .map(|expiry| DateTime::from(expiry).fmt(Format::DateTime))
+            .transpose()?;
+
+        Ok(Self {
+            access_key_id: credentials.access_key_id().to_string(),
+            secret_access_key: credentials.secret_access_key().to_string(),
+            token: credentials.session_token().map(ToString::to_string),
+            expiration,
+        })
+    }
+}
+
+/// Generate a random token for clients to authorize themselves with
+///
+/// # Errors
+///
+/// Will return `Err` if the operating system cannot provide random bytes
+pub fn generate_token() -> Result<String> {
+    let mut bytes = [0; 32];
+    getrandom::getrandom(&mut bytes).map_err(|e| eyre!("Could not generate token ({e})"))?;
+    Ok(HEXLOWER.encode(&bytes))
+}
+
+/// An HTTP server that hands out credentials for a profile to the AWS SDKs,
+/// in the format of the ECS container credentials endpoint.
+#[derive(Debug)]
+pub struct Server {
+    listener: TcpListener,
+    token: Arc<str>,
+    refresh_threshold: Duration,
+}
+
+impl Server {
+    /// # Errors
+    ///
+    /// Will return `Err` if the address cannot be listened on
+    pub async fn bind(
+        addr: SocketAddr,
+        token: String,
+        refresh_threshold: Duration,
+    ) -> Result<Self> {
+        Ok(Self {
+            listener: TcpListener::bind(addr).await?,
+            token: token.into(),
+            refresh_threshold,
+        })
+    }
+
+    /// The URL to set as `AWS_CONTAINER_CREDENTIALS_FULL_URI`
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the address of the listener cannot be determined
+    pub fn full_uri(&self) -> Result<String> {
+        Ok(format!(
+            "http://{}{CREDENTIALS_PATH}",
+            self.listener.local_addr()?
+        ))
+    }
+
+    /// Serve credentials until an error occurs while accepting connections.
+    ///
+    /// Credentials are fetched with `fetch` up front, and again once they are
+    /// within the refresh threshold of expiring. Requests are answered while a refresh is
+    /// in progress, until the credentials being served expire.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the first credentials cannot be fetched.
+    /// Will return `Err` if a connection cannot be accepted.
+    #[instrument(skip_all)]
+    pub async fn run<F, Fut>(self, mut fetch: F) -> Result<()>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<Credentials>>,
+    {
+        let mut credentials = fetch().await?;
+        let mut refresh_at = self.refresh_at(&credentials);
+        // A refresh can wait on the user logging in again, so it runs alongside serving requests
+        let mut refreshing: Option<Pin<Box<Fut>>> = None;
+
+        loop {
+            tokio::select! {
+                // Use refreshed credentials as soon as they are ready
+                biased;
+
+                refreshed = async {
+                    match refreshing.as_mut() {
+                        Some(refresh) => refresh.await,
+                        None => pending().await,
+                    }
+                } => {
+                    refreshing = None;
+                    match refreshed {
+                        Ok(refreshed) => {
+                            info!("Refreshed credentials");
+                            credentials = refreshed;
+                            refresh_at = self.refresh_at(&credentials);
+                        }
+                        Err(e) => {
+                            error!("Failed to refresh credentials ({e})");
+                            refresh_at = Some(Instant::now() + RETRY_INTERVAL);
+                        }
+                    }
+                }
+                () = sleep_until(refresh_at.unwrap_or_else(far_future)), if refreshing.is_none() => {
+                    refreshing = Some(Box::pin(fetch()));
+                }
+                accepted = self.listener.accept() => {
+                    let (stream, peer) = accepted?;
+                    debug!("Connection from {peer}");
+
+                    // Respond separately, so that a slow client does not hold up others
+                    let token = Arc::clone(&self.token);
+                    let credentials = credentials.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) = respond(stream, &token, &credentials).await {
+                            warn!("Failed to respond to {peer} ({e})");
+                        }
+                    });
+                }
+            }
+        }
+    }
+
+    /// When to refresh credentials, or `None` if they do not expire
+    fn refresh_at(&self, credentials: &Credentials) -> Option<Instant> {
+        let remaining = credentials
+            .expiry()?
+            .duration_since(SystemTime::now())
+            .unwrap_or_default();
+
+        Some(Instant::now() + remaining.saturating_sub(self.refresh_threshold))
+    }
+}
+
+/// Answer a request for `credentials`, authorized by `token`.
+/// Once the credentials have expired (because they could not be refreshed), there are none to give.
+async fn respond(mut stream: TcpStream, token: &str, credentials: &Credentials) -> Result<()> {
+    let request = timeout(REQUEST_TIMEOUT, read_request(&mut stream))
+        .await
+        .map_err(|_| eyre!("Timed out reading request"))??;
+
+    let (status, body) = match Request::parse(&request) {
+        None => ("400 Bad Request", String::new()),
+        Some(request) if request.method != "GET" || request.path != CREDENTIALS_PATH => {
+            ("404 Not Found", String::new())
+        }
+        Some(request)
+            if !request
+                .authorization
+                .is_some_and(|authorization| constant_time_eq(authorization, token)) =>
+        {
+            ("401 Unauthorized", String::new())
+        }
+        Some(_)
+            if credentials
+                .expiry()
+                .is_some_and(|expiry| expiry <= SystemTime::now()) =>
+        {
+            ("503 Service Unavailable", String::new())
+        }
+        Some(_) => (
+            "200 OK",
+            serde_json::to_string(&ContainerCredentials::try_from(credentials)?)?,
+        ),
+    };
+
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await?;
+
+    Ok(())
+}
+
+/// Far enough away that it is never reached (used when credentials never expire)
+fn far_future() -> Instant {
+    Instant::now() + Duration::from_secs(60 * 60 * 24 * 365)
+}
+
+/// Read an HTTP request, up to the end of its headers
+async fn read_request(stream: &mut TcpStream) -> Result<String> {
+    let mut request = Vec::new();
+    let mut buffer = [0; 1024];
+
+    while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+        let read = stream.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        request.extend_from_slice(&buffer[..read]);
+        if request.len() > MAX_REQUEST_SIZE {
+            return Err(eyre!("Request too large"));
+        }
+    }
+
+    Ok(String::from_utf8_lossy(&request).into_owned())
+}
+
+struct Request<'a> {
+    method: &'a str,
+    path: &'a str,
+    authorization: Option<&'a str>,
+}
+
+impl<'a> Request<'a> {
+    fn parse(request: &'a str) -> Option<Self> {
+        let mut lines = request.lines();
+        let mut request_line = lines.next()?.split_whitespace();
+        let method = request_line.next()?;
+        let path = request_line.next()?;
+
+        let authorization = lines
+            .take_while(|line| !line.is_empty())
+            .filter_map(|line| line.split_once(':'))
+            .find(|(name, _)| name.trim().eq_ignore_ascii_case("authorization"))
+            .map(|(_, value)| value.trim());
+
+        Some(Self {
+            method,
+            path,
+            authorization,
+        })
+    }
+}
+
+/// Compare tokens without leaking how much of them matched through timing
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |difference, (x, y)| difference | (x ^ y))
+            == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::UNIX_EPOCH;
+
+    fn credentials() -> Credentials {
+        Credentials::new(
+            "ACCESS_KEY",
+            "SECRET_ACCESS_KEY",
+            Some("SESSION_TOKEN".to_string()),
+            Some(UNIX_EPOCH + Duration::from_secs(4_000_000_000)),
+            "oktaws",
+        )
+    }
+
+    #[test]
+    fn generates_distinct_tokens() -> Result<()> {
+        let token = generate_token()?;
+        assert_eq!(token.len(), 64);
+        assert_ne!(token, generate_token()?);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn serves_credentials() -> Result<()> {
+        let server = Server::bind(
+            "127.0.0.1:0".parse()?,
+            "TOKEN".to_string(),
+            Duration::from_secs(600),
+        )
+        .await?;
+        let uri = server.full_uri()?;
+        tokio::spawn(server.run(|| async { Ok(credentials()) }));
+
+        let client = reqwest::Client::new();
+
+        let response = client
+            .get(&uri)
+            .header("Authorization", "TOKEN")
+            .send()
+            .await?;
+        assert_eq!(response.status(), 200);
+        assert_eq!(
+            response.json::<serde_json::Value>().await?,
+            serde_json::json!({
+                "AccessKeyId": "ACCESS_KEY",
+                "SecretAccessKey": "SECRET_ACCESS_KEY",
+                "Token": "SESSION_TOKEN",
+                "Expiration": "2096-10-02T07:06:40Z"
+            })
+        );
+
+        let response = client
+            .get(&uri)
+            .header("Authorization", "WRONG")
+            .send()
+            .await?;
+        assert_eq!(response.status(), 401);
+
+        let response = client.get(&uri).send().await?;
+        assert_eq!(response.status(), 401);
+
+        let response = client
+            .get(uri.replace(CREDENTIALS_PATH, "/other"))
+            .header("Authorization", "TOKEN")
+            .send()
+            .await?;
+        assert_eq!(response.status(), 404);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn refreshes_expiring_credentials() -> Result<()> {
+        let server = Server::bind(
+            "127.0.0.1:0".parse()?,
+            "TOKEN".to_string(),
+            Duration::from_secs(600),
+        )
+        .await?;
+        let uri = server.full_uri()?;
+
+        let fetches = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let counter = fetches.clone();
+        tokio::spawn(server.run(move || {
+            let fetch = counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            async move {
+                // The first credentials are already within the refresh threshold
+                let lifetime = if fetch == 0 { 60 } else { 3600 };
+                Ok(Credentials::new(
+                    format!("ACCESS_KEY_{fetch}"),
+                    "SECRET_ACCESS_KEY",
+                    None,
+                    Some(SystemTime::now() + Duration::from_secs(lifetime)),
+                    "oktaws",
+                ))
+            }
+        }));
+
+        let response = reqwest::Client::new()
+            .get(&uri)
+            .header("Authorization", "TOKEN")
+            .send()
+            .await?
+            .json::<serde_json::Value>()
+            .await?;
+        assert_eq!(response["AccessKeyId"], "ACCESS_KEY_1");
+        assert_eq!(fetches.load(std::sync::atomic::Ordering::SeqCst), 2);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn serves_while_refreshing() -> Result<()> {
+        let server = Server::bind(
+            "127.0.0.1:0".parse()?,
+            "TOKEN".to_string(),
+            Duration::from_secs(600),
+        )
+        .await?;
+        let uri = server.full_uri()?;
+
+        let mut fetched = false;
+        tokio::spawn(server.run(move || {
+            let first = !std::mem::replace(&mut fetched, true);
+            async move {
+                if !first {
+                    // Like waiting on the user to log in again
+                    pending::<()>().await;
+                }
+                // Still valid, but within the refresh threshold
+                Ok(Credentials::new(
+                    "ACCESS_KEY",
+                    "SECRET_ACCESS_KEY",
+                    None,
+                    Some(SystemTime::now() + Duration::from_secs(60)),
+                    "oktaws",
+                ))
+            }
+        }));
+
+        let response = timeout(
+            Duration::from_secs(2),
+            reqwest::Client::new()
+                .get(&uri)
+                .header("Authorization", "TOKEN")
+                .send(),
+        )
+        .await??;
+        assert_eq!(response.status(), 200);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn refuses_expired_credentials() -> Result<()> {
+        let server = Server::bind(
+            "127.0.0.1:0".parse()?,
+            "TOKEN".to_string(),
+            Duration::from_secs(600),
+        )
+        .await?;
+        let uri = server.full_uri()?;
+
+        let mut fetched = false;
+        tokio::spawn(server.run(move || {
+            let first = !std::mem::replace(&mut fetched, true);
+            async move {
+                if first {
+                    Ok(Credentials::new(
+                        "ACCESS_KEY",
+                        "SECRET_ACCESS_KEY",
+                        None,
+                        Some(SystemTime::now() - Duration::from_secs(1)),
+                        "oktaws",
+                    ))
+                } else {
+                    Err(eyre!("Login required"))
+                }
+            }
+        }));
+
+        let response = reqwest::Client::new()
+            .get(&uri)
+            .header("Authorization", "TOKEN")
+            .send()
+            .await?;
+        assert_eq!(response.status(), 503);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn slow_clients_do_not_hold_up_others() -> Result<()> {
+        let server = Server::bind(
+            "127.0.0.1:0".parse()?,
+            "TOKEN".to_string(),
+            Duration::from_secs(600),
+        )
+        .await?;
+        let addr = server.listener.local_addr()?;
+        let uri = server.full_uri()?;
+        tokio::spawn(server.run(|| async { Ok(credentials()) }));
+
+        // Connect, but never send a request
+        let _slow = TcpStream::connect(addr).await?;
+
+        let response = timeout(
+            Duration::from_secs(2),
+            reqwest::Client::new()
+                .get(&uri)
+                .header("Authorization", "TOKEN")
+                .send(),
+        )
+        .await??;
+        assert_eq!(response.status(), 200);
+
+        Ok(())
+    }
+}
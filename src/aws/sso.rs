@@ -1,3 +1,6 @@
+use crate::aws::sso_cache::CachedToken;
+
+use aws_credential_types::Credentials;
 use eyre::{Result, eyre};
 use futures::future::join_all;
 use regex::Regex;
@@ -5,10 +8,24 @@ use reqwest_middleware::{ClientBuilder, ClientWithMiddleware};
 use reqwest_retry::{RetryTransientMiddleware, policies::ExponentialBackoff};
 use serde::Deserialize;
 use std::sync::LazyLock;
-use std::time::Duration;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tracing::{debug, trace};
 
-const BASE_URL: &str = "https://portal.sso.us-east-1.amazonaws.com";
+/// The region of an IAM Identity Center instance, if it cannot be detected
+pub const DEFAULT_SSO_REGION: &str = "us-east-1";
+
+/// The portal API endpoint for an IAM Identity Center instance in `region`
+#[must_use]
+pub fn portal_url(region: &str) -> String {
+    format!("https://portal.sso.{region}.amazonaws.com")
+}
+
+/// How long a portal token is assumed to remain valid for.
+///
+/// The `auth/sso-token` response only holds the token, not when it expires.
+/// Tokens last as long as the Identity Center instance's session duration,
+/// which can be set as low as 15 minutes, so that is all that can be relied on.
+const PORTAL_TOKEN_LIFETIME: Duration = Duration::from_secs(15 * 60);
 
 fn retrying_client(max_retries: u32) -> ClientWithMiddleware {
     let retry_policy = ExponentialBackoff::builder()
@@ -22,6 +39,7 @@ fn retrying_client(max_retries: u32) -> ClientWithMiddleware {
 }
 
 pub struct Client {
+    base_url: String,
     token: String,
 }
 
@@ -88,20 +106,43 @@ struct RolesPage {
     next_token: Option<String>,
 }
 
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RoleCredentialsResponse {
+    role_credentials: RoleCredentials,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RoleCredentials {
+    access_key_id: String,
+    secret_access_key: String,
+    session_token: String,
+    /// Milliseconds since the epoch
+    expiration: u64,
+}
+
+/// Normalize an account name the way `init` writes it into profile names
+fn normalize_account_name(account_name: &str) -> String {
+    account_name.to_lowercase().replace([' ', '_'], "-")
+}
+
 impl Client {
     /// # Errors
     ///
     /// The function will error for network issues, or if the response is not parseable as expected
     ///
-    pub async fn new(org_id: &str, auth_code: &str) -> Result<Self> {
+    pub async fn new(org_id: &str, auth_code: &str, region: &str) -> Result<Self> {
         #[derive(Deserialize)]
         struct SsoTokenResponse {
             token: String,
         }
 
+        let base_url = portal_url(region);
+
         // Get SSO Token
         let response = reqwest::Client::new()
-            .post(format!("{BASE_URL}/auth/sso-token"))
+            .post(format!("{base_url}/auth/sso-token"))
             .form(&[("authCode", auth_code), ("orgId", org_id)])
             .send()
             .await?;
@@ -111,7 +152,22 @@ impl Client {
 
         let SsoTokenResponse { token } = serde_json::from_str(&text)?;
 
-        Ok(Self { token })
+        Ok(Self { base_url, token })
+    }
+
+    /// The portal token held by this client, for the AWS CLI and SDKs to use
+    /// with the `sso-session` for `start_url`
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the expiry cannot be formatted as a timestamp
+    pub fn cached_token(&self, start_url: &str, region: &str) -> Result<CachedToken> {
+        CachedToken::new(
+            start_url,
+            region,
+            &self.token,
+            SystemTime::now() + PORTAL_TOKEN_LIFETIME,
+        )
     }
 
     /// Retrieve account/role mappings using the portal bearer token already held by this client.
@@ -209,7 +265,7 @@ impl Client {
         role_names.dedup();
         Ok(PublicAccountRole {
             account_id,
-            account_name: account_name.to_lowercase().replace([' ', '_'], "-"),
+            account_name: normalize_account_name(&account_name),
             role_names,
         })
     }
@@ -219,7 +275,8 @@ impl Client {
     /// The function will error for API/network failures.
     pub async fn list_accounts_and_roles(&self) -> Result<Vec<PublicAccountRole>> {
         let http = retrying_client(5);
-        self.list_accounts_and_roles_with(&http, BASE_URL).await
+        self.list_accounts_and_roles_with(&http, &self.base_url)
+            .await
     }
 
     async fn list_accounts_and_roles_with(
@@ -264,13 +321,107 @@ impl Client {
         Ok(results)
     }
 
+    /// Find the ID of an account, given either its ID or its (normalized) name
+    ///
+    /// # Errors
+    ///
+    /// The function will error for API/network failures, or if no such account is assigned to the user.
+    pub async fn account_id(&self, account: &str) -> Result<String> {
+        let http = retrying_client(5);
+        self.account_id_with(&http, &self.base_url, account).await
+    }
+
+    async fn account_id_with(
+        &self,
+        http: &ClientWithMiddleware,
+        base_url: &str,
+        account: &str,
+    ) -> Result<String> {
+        self.fetch_all_accounts(http, base_url)
+            .await?
+            .into_iter()
+            .find(|(account_id, account_name)| {
+                account_id == account || normalize_account_name(account_name) == account
+            })
+            .map(|(account_id, _)| account_id)
+            .ok_or_else(|| eyre!("Account {} not found in IAM Identity Center", account))
+    }
+
+    /// The names of the roles the user can assume in an account
+    ///
+    /// # Errors
+    ///
+    /// The function will error for API/network failures.
+    pub async fn account_roles(&self, account_id: &str) -> Result<Vec<String>> {
+        Ok(Self::fetch_account_roles(
+            retrying_client(5),
+            self.base_url.clone(),
+            self.token.clone(),
+            account_id.to_string(),
+            String::new(),
+        )
+        .await?
+        .role_names)
+    }
+
+    /// Retrieve temporary credentials for a role in an account (`GetRoleCredentials`),
+    /// using the portal bearer token already held by this client.
+    ///
+    /// # Errors
+    ///
+    /// The function will error for API/network failures, or if the role cannot be assumed.
+    pub async fn role_credentials(&self, account_id: &str, role_name: &str) -> Result<Credentials> {
+        let http = retrying_client(5);
+        self.role_credentials_with(&http, &self.base_url, account_id, role_name)
+            .await
+    }
+
+    async fn role_credentials_with(
+        &self,
+        http: &ClientWithMiddleware,
+        base_url: &str,
+        account_id: &str,
+        role_name: &str,
+    ) -> Result<Credentials> {
+        let response = http
+            .get(format!("{base_url}/federation/credentials"))
+            .header("x-amz-sso_bearer_token", &self.token)
+            .query(&[("account_id", account_id), ("role_name", role_name)])
+            .send()
+            .await?;
+
+        let status = response.status();
+        let text = response.text().await?;
+        if !status.is_success() {
+            return Err(eyre!(
+                "GetRoleCredentials failed for {} in {} ({}): {}",
+                role_name,
+                account_id,
+                status,
+                text
+            ));
+        }
+
+        let RoleCredentialsResponse {
+            role_credentials: credentials,
+        } = serde_json::from_str(&text)?;
+
+        Ok(Credentials::new(
+            credentials.access_key_id,
+            credentials.secret_access_key,
+            Some(credentials.session_token),
+            Some(UNIX_EPOCH + Duration::from_millis(credentials.expiration)),
+            "sso",
+        ))
+    }
+
     /// # Errors
     ///
     /// The function will error for network issues, or if the response is not parseable as expected
     ///
     pub async fn app_instances(&self) -> Result<Vec<AppInstance>> {
         let client = retrying_client(5);
-        self.app_instances_with(&client, BASE_URL).await
+        self.app_instances_with(&client, &self.base_url).await
     }
 
     async fn app_instances_with(
@@ -329,7 +480,8 @@ impl Client {
 
         let response = client
             .get(format!(
-                "{BASE_URL}/instance/appinstance/{app_instance_id}/profiles"
+                "{}/instance/appinstance/{app_instance_id}/profiles",
+                self.base_url
             ))
             .header("x-amz-sso_bearer_token", &self.token)
             .header("x-amz-sso-bearer-token", &self.token)
@@ -652,6 +804,7 @@ mod tests {
             .await;
 
         let client = Client {
+            base_url: server.uri(),
             token: "test-token".to_string(),
         };
         let accounts = client
@@ -689,6 +842,7 @@ mod tests {
             .await;
 
         let client = Client {
+            base_url: server.uri(),
             token: "test-token".to_string(),
         };
         let accounts = client
@@ -741,6 +895,7 @@ mod tests {
             .await;
 
         let client = Client {
+            base_url: server.uri(),
             token: "test-token".to_string(),
         };
         let instances = client
@@ -756,4 +911,66 @@ mod tests {
             vec!["instance-1", "instance-2"]
         );
     }
+
+    #[tokio::test]
+    async fn test_role_credentials_for_named_account() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/assignment/accounts"))
+            .and(header("x-amz-sso_bearer_token", "test-token"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "accountList": [{
+                    "accountId": "222222222222",
+                    "accountName": "Shared_Services"
+                }]
+            })))
+            .expect(2)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/federation/credentials"))
+            .and(header("x-amz-sso_bearer_token", "test-token"))
+            .and(query_param("account_id", "222222222222"))
+            .and(query_param("role_name", "PowerUser"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "roleCredentials": {
+                    "accessKeyId": "ACCESS_KEY",
+                    "secretAccessKey": "SECRET_ACCESS_KEY",
+                    "sessionToken": "SESSION_TOKEN",
+                    "expiration": 1_700_000_000_000_u64
+                }
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = Client {
+            base_url: server.uri(),
+            token: "test-token".to_string(),
+        };
+        let http = fast_retrying_client(1);
+        let account_id = client
+            .account_id_with(&http, &server.uri(), "shared-services")
+            .await
+            .unwrap();
+        assert_eq!(account_id, "222222222222");
+        assert!(
+            client
+                .account_id_with(&http, &server.uri(), "missing")
+                .await
+                .is_err()
+        );
+
+        let credentials = client
+            .role_credentials_with(&http, &server.uri(), &account_id, "PowerUser")
+            .await
+            .unwrap();
+        assert_eq!(credentials.access_key_id(), "ACCESS_KEY");
+        assert_eq!(credentials.secret_access_key(), "SECRET_ACCESS_KEY");
+        assert_eq!(credentials.session_token(), Some("SESSION_TOKEN"));
+        assert_eq!(
+            credentials.expiry(),
+            Some(UNIX_EPOCH + Duration::from_secs(1_700_000_000))
+        );
+    }
 }
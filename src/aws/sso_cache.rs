@@ -0,0 +1,189 @@
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use aws_smithy_types::DateTime;
+use aws_smithy_types::date_time::Format;
+use data_encoding::HEXLOWER;
+use eyre::{Result, eyre};
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+use tracing::instrument;
+
+/// The SSO token cache (`~/.aws/sso/cache`) that the AWS CLI and SDKs
+/// read access tokens for `sso-session`s from.
+#[derive(Debug)]
+pub struct TokenCache {
+    dir: PathBuf,
+}
+
+/// A cached access token, in the format the AWS CLI and SDKs expect
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct CachedToken {
+    pub start_url: String,
+    pub region: String,
+    pub access_token: String,
+    /// RFC 3339 timestamp, in UTC
+    pub expires_at: String,
+}
+
+impl CachedToken {
+    /// # Errors
+    ///
+    /// Will return `Err` if the expiry cannot be formatted as a timestamp
+    pub fn new(
+        start_url: &str,
+        region: &str,
+        access_token: &str,
+        expires_at: SystemTime,
+    ) -> Result<Self> {
+        Ok(Self {
+            start_url: start_url.to_string(),
+            region: region.to_string(),
+            access_token: access_token.to_string(),
+            expires_at: DateTime::from(expires_at).fmt(Format::DateTime)?,
+        })
+    }
+}
+
+impl TokenCache {
+    /// Locate the cache, defaulting to `~/.aws/sso/cache`
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if no directory is given and the HOME environment variable is not set.
+    #[instrument]
+    pub fn load(dir: Option<&Path>) -> Result<Self> {
+        let dir = match dir {
+            Some(dir) => PathBuf::from(dir),
+            None => dirs::home_dir().map_or_else(
+                || Err(eyre!("The environment variable HOME must be set.")),
+                |home_dir| Ok(home_dir.join(".aws").join("sso").join("cache")),
+            )?,
+        };
+
+        Ok(Self { dir })
+    }
+
+    /// The file the token for an `sso-session` is cached in, named by the SHA-1 of the session name
+    #[must_use]
+    pub fn path(&self, session_name: &str) -> PathBuf {
+        let digest = Sha1::digest(session_name.as_bytes());
+        self.dir.join(format!("{}.json", HEXLOWER.encode(&digest)))
+    }
+
+    /// # Errors
+    ///
+    /// Will return `Err` if the cache directory cannot be created.
+    /// Will return `Err` if the token cannot be written.
+    #[instrument(skip(self, token))]
+    pub fn store(&self, session_name: &str, token: &CachedToken) -> Result<()> {
+        fs::create_dir_all(&self.dir)?;
+
+        let contents = serde_json::to_string_pretty(token)?;
+        create_private(&self.path(session_name))
+            .and_then(|mut file| file.write_all(contents.as_bytes()))
+            .map_err(|e| eyre!("Failed to write SSO token cache: {}", e))
+    }
+}
+
+/// Create (or truncate) a file that only the current user can read, as the AWS CLI does for cached tokens
+#[cfg(unix)]
+fn create_private(path: &Path) -> std::io::Result<File> {
+    use std::fs::Permissions;
+    use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+
+    let file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?;
+    // The mode only applies to new files
+    file.set_permissions(Permissions::from_mode(0o600))?;
+    Ok(file)
+}
+
+#[cfg(not(unix))]
+fn create_private(path: &Path) -> std::io::Result<File> {
+    File::create(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use tempfile::TempDir;
+
+    #[test]
+    fn names_files_by_session() -> Result<()> {
+        let dir = TempDir::new()?;
+        let cache = TokenCache::load(Some(dir.path()))?;
+
+        assert_eq!(
+            cache.path("my-sso"),
+            dir.path()
+                .join("0ad374308c5a4e22f723adf10145eafad7c4031c.json")
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn stores_tokens() -> Result<()> {
+        let dir = TempDir::new()?;
+        let cache = TokenCache::load(Some(&dir.path().join("sso").join("cache")))?;
+        let token = CachedToken::new(
+            "https://d-1234567890.awsapps.com/start",
+            "eu-west-1",
+            "ACCESS_TOKEN",
+            UNIX_EPOCH + Duration::from_secs(1_700_000_000),
+        )?;
+
+        cache.store("my-sso", &token)?;
+
+        let written: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(cache.path("my-sso"))?)?;
+        assert_eq!(
+            written,
+            serde_json::json!({
+                "startUrl": "https://d-1234567890.awsapps.com/start",
+                "region": "eu-west-1",
+                "accessToken": "ACCESS_TOKEN",
+                "expiresAt": "2023-11-14T22:13:20Z"
+            })
+        );
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn stores_tokens_privately() -> Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = TempDir::new()?;
+        let cache = TokenCache::load(Some(dir.path()))?;
+        let token = CachedToken::new(
+            "https://d-1234567890.awsapps.com/start",
+            "eu-west-1",
+            "ACCESS_TOKEN",
+            UNIX_EPOCH,
+        )?;
+
+        // Including over a token cached before with looser permissions
+        fs::write(cache.path("my-sso"), "{}")?;
+        fs::set_permissions(cache.path("my-sso"), fs::Permissions::from_mode(0o644))?;
+
+        cache.store("my-sso", &token)?;
+
+        let mode = fs::metadata(cache.path("my-sso"))?.permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        Ok(())
+    }
+}
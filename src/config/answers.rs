@@ -0,0 +1,162 @@
+use std::str::FromStr;
+
+use eyre::{Error, Result, eyre};
+
+/// A role to choose for one account, given as `ACCOUNT=ROLE`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RoleFor {
+    pub account: String,
+    pub role: String,
+}
+
+impl FromStr for RoleFor {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.rsplit_once('=') {
+            Some((account, role)) if !account.is_empty() && !role.is_empty() => Ok(Self {
+                account: account.to_string(),
+                role: role.to_string(),
+            }),
+            _ => Err(eyre!("Expected ACCOUNT=ROLE, got {s}")),
+        }
+    }
+}
+
+/// Answers, given up front, to the questions that `init` and `init-sso` would otherwise ask
+#[derive(Clone, Debug, Default)]
+pub struct Answers {
+    /// Answer yes to confirmations
+    pub yes: bool,
+    /// Roles to use by default, in order of preference
+    pub default_roles: Vec<String>,
+    /// Roles to use for particular accounts, over any default
+    pub roles_for: Vec<RoleFor>,
+    /// Fail, rather than prompt, for anything that has not been answered
+    pub no_input: bool,
+}
+
+impl Answers {
+    /// The role chosen for `account`, if any
+    #[must_use]
+    pub fn role_for(&self, account: &str) -> Option<&str> {
+        self.roles_for
+            .iter()
+            .find(|role_for| role_for.account == account)
+            .map(|role_for| role_for.role.as_str())
+    }
+
+    /// The role chosen for `account`, checked against the roles it has
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if a role was chosen for the account, but it does not have that role
+    pub fn available_role_for(&self, account: &str, roles: &[String]) -> Result<Option<String>> {
+        match self.role_for(account) {
+            Some(role) if roles.iter().any(|available| available == role) => {
+                Ok(Some(role.to_string()))
+            }
+            Some(role) => Err(eyre!(
+                "Role {role} is not available for {account} (available roles: {})",
+                roles.join(", ")
+            )),
+            None => Ok(None),
+        }
+    }
+
+    /// Check that `question` may be asked
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if prompting is not allowed
+    pub fn allow_prompt(&self, question: &str) -> Result<()> {
+        if self.no_input {
+            Err(eyre!("{question} is needed, but --no-input was given"))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Ask for confirmation, unless it was given with `yes`
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if prompting is not allowed, or the prompt fails
+    pub fn confirm(&self, question: &str, default: bool) -> Result<bool> {
+        if self.yes {
+            return Ok(true);
+        }
+
+        self.allow_prompt(&format!("An answer to \"{question}\" (or --yes)"))?;
+
+        Ok(dialoguer::Confirm::new()
+            .with_prompt(question)
+            .default(default)
+            .interact()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_roles_for_accounts() -> Result<()> {
+        assert_eq!(
+            "prod=AdminAccess".parse::<RoleFor>()?,
+            RoleFor {
+                account: "prod".to_string(),
+                role: "AdminAccess".to_string(),
+            }
+        );
+        assert_eq!(
+            "my=account=ReadOnly".parse::<RoleFor>()?,
+            RoleFor {
+                account: "my=account".to_string(),
+                role: "ReadOnly".to_string(),
+            }
+        );
+        assert!("prod".parse::<RoleFor>().is_err());
+        assert!("prod=".parse::<RoleFor>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn checks_roles_for_accounts() -> Result<()> {
+        let answers = Answers {
+            roles_for: vec!["prod=AdminAccess".parse()?],
+            ..Answers::default()
+        };
+        let roles = ["AdminAccess".to_string(), "ReadOnly".to_string()];
+
+        assert_eq!(
+            answers.available_role_for("prod", &roles)?,
+            Some("AdminAccess".to_string())
+        );
+        assert_eq!(answers.available_role_for("staging", &roles)?, None);
+        assert!(
+            answers
+                .available_role_for("prod", &["ReadOnly".to_string()])
+                .is_err()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn refuses_to_prompt_without_input() -> Result<()> {
+        let answers = Answers {
+            no_input: true,
+            ..Answers::default()
+        };
+        assert!(answers.allow_prompt("A username").is_err());
+        assert!(answers.confirm("Write config?", true).is_err());
+
+        let answers = Answers {
+            yes: true,
+            no_input: true,
+            ..Answers::default()
+        };
+        assert!(answers.confirm("Write config?", false)?);
+        Ok(())
+    }
+}
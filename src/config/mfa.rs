@@ -0,0 +1,270 @@
+use crate::okta::factors::Factor;
+
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+use eyre::{Error, Result, eyre};
+use serde::{Deserialize, Serialize};
+
+/// MFA configuration, loaded from the `[mfa]` section of `~/.oktaws/<org>.toml`.
+///
+/// ```toml
+/// [mfa]
+/// factors = [
+///   { type = "push", device = "My iPhone" },
+///   { type = "totp", provider = "google" },
+/// ]
+/// ```
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct MfaConfig {
+    /// Factors to use without prompting, in order of preference
+    #[serde(default)]
+    pub factors: Vec<FactorPreference>,
+}
+
+impl MfaConfig {
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.factors.is_empty()
+    }
+}
+
+/// The kinds of MFA factor that Okta supports
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum FactorKind {
+    Push,
+    Sms,
+    Call,
+    Token,
+    Totp,
+    Hotp,
+    Question,
+    Web,
+    WebAuthn,
+}
+
+impl FromStr for FactorKind {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match &*s.to_lowercase() {
+            "push" => Ok(Self::Push),
+            "sms" => Ok(Self::Sms),
+            "call" => Ok(Self::Call),
+            "token" => Ok(Self::Token),
+            "totp" => Ok(Self::Totp),
+            "hotp" => Ok(Self::Hotp),
+            "question" => Ok(Self::Question),
+            "web" => Ok(Self::Web),
+            "webauthn" => Ok(Self::WebAuthn),
+            _ => Err(eyre!(
+                "Unknown MFA factor type {s} (expected one of push, sms, call, token, totp, hotp, question, web, webauthn)"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for FactorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Push => write!(f, "push"),
+            Self::Sms => write!(f, "sms"),
+            Self::Call => write!(f, "call"),
+            Self::Token => write!(f, "token"),
+            Self::Totp => write!(f, "totp"),
+            Self::Hotp => write!(f, "hotp"),
+            Self::Question => write!(f, "question"),
+            Self::Web => write!(f, "web"),
+            Self::WebAuthn => write!(f, "webauthn"),
+        }
+    }
+}
+
+/// A factor to pick automatically, when Okta offers one that matches
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct FactorPreference {
+    #[serde(rename = "type")]
+    pub kind: FactorKind,
+    /// Factor provider (such as `okta` or `google`), matched case-insensitively
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provider: Option<String>,
+    /// Device name (for Push), authenticator name (for WebAuthn), or phone number (for SMS and Call)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device: Option<String>,
+}
+
+/// Parse a preference from the CLI, in the form `TYPE[:PROVIDER[:DEVICE]]`
+impl FromStr for FactorPreference {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parts = s.splitn(3, ':');
+
+        Ok(Self {
+            kind: parts.next().unwrap_or_default().parse()?,
+            provider: parts
+                .next()
+                .filter(|provider| !provider.is_empty())
+                .map(ToString::to_string),
+            device: parts
+                .next()
+                .filter(|device| !device.is_empty())
+                .map(ToString::to_string),
+        })
+    }
+}
+
+impl FactorPreference {
+    #[must_use]
+    pub fn matches(&self, factor: &Factor) -> bool {
+        factor.kind() == self.kind
+            && self
+                .provider
+                .as_ref()
+                .is_none_or(|provider| factor.provider_name().eq_ignore_ascii_case(provider))
+            && self
+                .device
+                .as_ref()
+                .is_none_or(|device| factor.device_name() == Some(device.as_str()))
+    }
+}
+
+#[derive(Deserialize)]
+struct OktawsFile {
+    #[serde(default)]
+    mfa: Option<MfaConfig>,
+}
+
+/// Load just the `[mfa]` table from an oktaws org config file.
+///
+/// Returns `MfaConfig::default()` if the file does not exist or the `[mfa]`
+/// table is absent.
+///
+/// # Errors
+///
+/// Will return `Err` if the file exists but cannot be parsed as TOML.
+pub fn load_mfa_config(path: &Path) -> Result<MfaConfig> {
+    let raw = match std::fs::read_to_string(path) {
+        Ok(s) => s,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(MfaConfig::default()),
+        Err(e) => return Err(e.into()),
+    };
+    let parsed: OktawsFile = toml::from_str(&raw)?;
+    Ok(parsed.mfa.unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::json;
+
+    fn push_factor(device: &str) -> Factor {
+        serde_json::from_value(json!({
+            "id": "PUSH_ID",
+            "factorType": "push",
+            "provider": "OKTA",
+            "profile": { "name": device },
+            "_links": {}
+        }))
+        .unwrap()
+    }
+
+    fn totp_factor(provider: &str) -> Factor {
+        serde_json::from_value(json!({
+            "id": "TOTP_ID",
+            "factorType": "token:software:totp",
+            "provider": provider,
+            "profile": {},
+            "_links": {}
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn parses_mfa_table() -> Result<()> {
+        let config: MfaConfig = toml::from_str(
+            r#"
+            factors = [
+              { type = "push", device = "My iPhone" },
+              { type = "totp", provider = "google" },
+            ]
+            "#,
+        )?;
+
+        assert_eq!(
+            config.factors,
+            vec![
+                FactorPreference {
+                    kind: FactorKind::Push,
+                    provider: None,
+                    device: Some("My iPhone".to_string()),
+                },
+                FactorPreference {
+                    kind: FactorKind::Totp,
+                    provider: Some("google".to_string()),
+                    device: None,
+                },
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn parses_cli_preferences() -> Result<()> {
+        assert_eq!(
+            "push".parse::<FactorPreference>()?,
+            FactorPreference {
+                kind: FactorKind::Push,
+                provider: None,
+                device: None,
+            }
+        );
+        assert_eq!(
+            "push::My iPhone".parse::<FactorPreference>()?,
+            FactorPreference {
+                kind: FactorKind::Push,
+                provider: None,
+                device: Some("My iPhone".to_string()),
+            }
+        );
+        assert_eq!(
+            "TOTP:google".parse::<FactorPreference>()?,
+            FactorPreference {
+                kind: FactorKind::Totp,
+                provider: Some("google".to_string()),
+                device: None,
+            }
+        );
+        assert!("carrier-pigeon".parse::<FactorPreference>().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn matches_factors() -> Result<()> {
+        let push: FactorPreference = "push".parse()?;
+        assert!(push.matches(&push_factor("My iPhone")));
+        assert!(!push.matches(&totp_factor("OKTA")));
+
+        let iphone: FactorPreference = "push::My iPhone".parse()?;
+        assert!(iphone.matches(&push_factor("My iPhone")));
+        assert!(!iphone.matches(&push_factor("My iPad")));
+
+        let google: FactorPreference = "totp:google".parse()?;
+        assert!(google.matches(&totp_factor("GOOGLE")));
+        assert!(!google.matches(&totp_factor("OKTA")));
+
+        Ok(())
+    }
+
+    #[test]
+    fn load_mfa_config_missing_file() -> Result<()> {
+        let config = load_mfa_config(Path::new("THIS PATH DOES NOT EXIST"))?;
+        assert!(config.is_empty());
+        Ok(())
+    }
+}
@@ -1,3 +1,5 @@
+pub mod answers;
+pub mod mfa;
 pub mod organization;
 pub mod profile;
 pub mod sso;
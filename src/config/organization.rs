@@ -1,7 +1,12 @@
+use crate::config::answers::Answers;
+use crate::config::mfa::{FactorPreference, MfaConfig};
 use crate::config::oktaws_home;
-use crate::config::profile::{self, Profile};
+use crate::config::profile::{self, PortalClients, Profile};
 #[double]
 use crate::okta::client::Client as OktaClient;
+use crate::okta::client::LoginOptions;
+use crate::okta::default_base_url;
+use crate::okta::idx::Pipeline;
 use crate::select_multiple_opt;
 use mockall_double::double;
 
@@ -19,17 +24,26 @@ use futures::stream::{self, StreamExt};
 use itertools::Itertools;
 use serde::{Deserialize, Serialize};
 use toml;
-use tracing::{debug, error, instrument};
+use tracing::{debug, instrument};
+use url::Url;
 use whoami::username;
 
 /// This is an intentionally 'loose' struct,
 /// representing the potential for overrides and later prompts
 #[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct Config {
+    /// Okta URL, for organizations not at `https://<name>.okta.com/`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base_url: Option<Url>,
     pub username: Option<String>,
     pub roles: Option<Vec<String>>,
     pub role: Option<String>,
     pub duration_seconds: Option<i32>,
+    /// Authentication pipeline to log in with (detected from the organization if not set)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pipeline: Option<Pipeline>,
+    #[serde(default, skip_serializing_if = "MfaConfig::is_empty")]
+    pub mfa: MfaConfig,
     pub profiles: IndexMap<String, profile::Config>,
 }
 
@@ -41,7 +55,12 @@ impl Config {
     /// Will return `Err` if there are any errors fetching the information
     /// from Okta to form the config,
     /// or if there are errors during prompting of a default role.
-    pub async fn from_organization(client: &OktaClient, username: String) -> Result<Self> {
+    /// Will return `Err` if a role is needed that was not given in `answers`, and prompting is not allowed.
+    pub async fn from_organization(
+        client: &OktaClient,
+        username: String,
+        answers: &Answers,
+    ) -> Result<Self> {
         let app_links = client.app_links(None).await?;
         // Only use federated AWS apps (amazon_aws), not SSO apps
         let aws_links = app_links
@@ -73,8 +92,11 @@ impl Config {
             .map(|(_, x)| x)
             .collect::<Vec<_>>();
 
-        let default_roles = if default_role_names.is_empty() {
-            default_role_names
+        let default_roles = if !answers.default_roles.is_empty() {
+            answers.default_roles.clone()
+        } else if default_role_names.is_empty() || answers.no_input {
+            // Choosing no default roles is what the prompt defaults to
+            Vec::new()
         } else {
             select_multiple_opt(
                 default_role_names,
@@ -86,32 +108,41 @@ impl Config {
         let profiles = all_account_role_mappings
             .into_iter()
             .map(|account_mapping| {
-                profile::Config::from_account_mapping(account_mapping, &default_roles)
+                profile::Config::from_account_mapping(account_mapping, &default_roles, answers)
             })
             .collect::<Result<IndexMap<String, profile::Config>, Error>>()?;
 
         if default_roles.is_empty() {
             Ok(Self {
+                base_url: None,
                 username: Some(username),
                 duration_seconds: None,
                 role: None,
                 roles: None,
+                pipeline: None,
+                mfa: MfaConfig::default(),
                 profiles,
             })
         } else if default_roles.len() == 1 {
             Ok(Self {
+                base_url: None,
                 username: Some(username),
                 duration_seconds: None,
                 role: default_roles.first().cloned(),
                 roles: None,
+                pipeline: None,
+                mfa: MfaConfig::default(),
                 profiles,
             })
         } else {
             Ok(Self {
+                base_url: None,
                 username: Some(username),
                 duration_seconds: None,
                 role: None,
                 roles: Some(default_roles),
+                pipeline: None,
+                mfa: MfaConfig::default(),
                 profiles,
             })
         }
@@ -123,7 +154,10 @@ impl Config {
 #[derive(Clone, Debug)]
 pub struct Organization {
     pub name: String,
+    pub base_url: Url,
     pub username: String,
+    pub factors: Vec<FactorPreference>,
+    pub pipeline: Option<Pipeline>,
     pub profiles: Vec<Profile>,
 }
 
@@ -157,25 +191,56 @@ impl TryFrom<&Path> for Organization {
 
         let profiles = cfg
             .profiles
-            .iter()
-            .map(|(name, profile_config)| {
-                Profile::try_from_spec(
-                    profile_config,
-                    name.clone(),
+            .keys()
+            .map(|name| {
+                Profile::try_from_specs(
+                    &cfg.profiles,
+                    name,
                     default_roles.clone(),
                     cfg.duration_seconds,
                 )
             })
             .collect::<Result<Vec<Profile>, Error>>()?;
 
+        let base_url = match cfg.base_url {
+            Some(base_url) => base_url,
+            None => default_base_url(&filename)?,
+        };
+
         Ok(Self {
+            base_url,
             name: filename,
             username,
+            factors: cfg.mfa.factors,
+            pipeline: cfg.pipeline,
             profiles,
         })
     }
 }
 
+/// The settings in an oktaws org config file that control how to log in
+#[derive(Debug, Default, Deserialize)]
+pub struct LoginSettings {
+    pub base_url: Option<Url>,
+    pub pipeline: Option<Pipeline>,
+}
+
+/// Load just the login settings from an oktaws org config file.
+///
+/// Returns `LoginSettings::default()` if the file does not exist.
+///
+/// # Errors
+///
+/// Will return `Err` if the file exists but cannot be parsed as TOML.
+pub fn load_login_settings(path: &Path) -> Result<LoginSettings> {
+    let raw = match std::fs::read_to_string(path) {
+        Ok(s) => s,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(LoginSettings::default()),
+        Err(e) => return Err(e.into()),
+    };
+    toml::from_str(&raw).map_err(Into::into)
+}
+
 /// Prompt for a username for a given Okta organization.
 ///
 /// # Errors
@@ -190,38 +255,74 @@ pub fn prompt_username(organization: &impl fmt::Display) -> Result<String> {
 }
 
 impl Organization {
+    /// Options for logging in to this organization.
+    /// Factors given on the command line take precedence over those configured.
+    #[must_use]
+    pub fn login_options(&self, force_prompt: bool, factors: &[FactorPreference]) -> LoginOptions {
+        LoginOptions {
+            force_prompt,
+            base_url: Some(self.base_url.clone()),
+            factors: if factors.is_empty() {
+                self.factors.clone()
+            } else {
+                factors.to_vec()
+            },
+            pipeline: self.pipeline,
+            no_input: false,
+        }
+    }
+
     pub fn into_profiles(self, filter: glob::Pattern) -> impl Iterator<Item = Profile> {
         self.profiles
             .into_iter()
             .filter(move |p| filter.matches(&p.name))
     }
 
+    /// Fetch credentials for the profiles matching `filter`, several at a time.
+    /// Profiles of the same Identity Center application share one portal token.
+    ///
+    /// With `fail_fast`, profiles still being fetched are abandoned after the first failure.
+    /// Every matching profile is returned, with no result if it was abandoned.
     #[instrument(skip_all, fields(organization=%self.name, profiles=%filter))]
     pub async fn into_credentials(
         self,
         client: &OktaClient,
         filter: glob::Pattern,
         role_override: Option<&String>,
-    ) -> impl Iterator<Item = (String, Credentials)> + use<> {
-        let futures = self.into_profiles(filter).map(|profile| async {
-            (
-                profile.name.clone(),
-                profile.into_credentials(client, role_override).await,
-            )
+        fail_fast: bool,
+    ) -> impl Iterator<Item = (String, Option<Result<Credentials>>)> + use<> {
+        let profiles = self.into_profiles(filter).collect::<Vec<_>>();
+        let mut abandoned = profiles
+            .iter()
+            .map(|profile| profile.name.clone())
+            .collect::<Vec<_>>();
+
+        let portal_clients = PortalClients::default();
+        let futures = profiles.into_iter().map(|profile| {
+            let portal_clients = &portal_clients;
+            async move {
+                (
+                    profile.name.clone(),
+                    profile
+                        .into_credentials(client, portal_clients, role_override)
+                        .await,
+                )
+            }
         });
 
-        stream::iter(futures)
-            .buffer_unordered(10) // Only run 10 concurrently at a time
-            .collect::<Vec<_>>()
-            .await
-            .into_iter()
-            .filter_map(|cred_result| match cred_result {
-                (profile, Ok(creds)) => Some((profile, creds)),
-                (_, Err(e)) => {
-                    error!("{e}");
-                    None
-                }
-            })
+        let mut results = Vec::new();
+        let mut credentials = stream::iter(futures).buffer_unordered(10); // Only run 10 concurrently at a time
+        while let Some((name, result)) = credentials.next().await {
+            let failed = result.is_err();
+            abandoned.retain(|abandoned_name| *abandoned_name != name);
+            results.push((name, Some(result)));
+            if failed && fail_fast {
+                break;
+            }
+        }
+
+        results.extend(abandoned.into_iter().map(|name| (name, None)));
+        results.into_iter()
     }
 }
 
@@ -261,6 +362,39 @@ impl Pattern {
 
         paths.iter().map(|p| p.as_path().try_into()).collect()
     }
+
+    /// Find the single organization that defines the profile `name`
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the organizations cannot be loaded,
+    /// or if the profile is not defined by exactly one organization.
+    pub fn profile(&self, name: &str) -> Result<(Organization, Profile)> {
+        let mut found = self
+            .organizations()?
+            .into_iter()
+            .filter_map(|organization| {
+                let profile = organization
+                    .profiles
+                    .iter()
+                    .find(|p| p.name == name)
+                    .cloned();
+                profile.map(|profile| (organization, profile))
+            })
+            .collect::<Vec<_>>();
+
+        match found.len() {
+            0 => Err(eyre!("No profile named {name} found in {self}")),
+            1 => Ok(found.remove(0)),
+            _ => Err(eyre!(
+                "Profile {name} is defined in multiple organizations ({}). Use --organizations to choose one",
+                found
+                    .iter()
+                    .map(|(organization, _)| &organization.name)
+                    .join(", ")
+            )),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -340,7 +474,9 @@ baz = {{ application = "baz", role = "baz_role" }}
             application_name: String::from("foo"),
             account: None,
             roles: vec![String::from("my_role"), String::from("my_role_2")],
-            duration_seconds: Some(300)
+            duration_seconds: Some(300),
+            sts_region: None,
+            chain: Vec::new()
         }));
 
         assert!(organization.profiles.contains(&Profile {
@@ -348,7 +484,9 @@ baz = {{ application = "baz", role = "baz_role" }}
             application_name: String::from("bar"),
             account: None,
             roles: vec![String::from("my_role"), String::from("my_role_2")],
-            duration_seconds: Some(600)
+            duration_seconds: Some(600),
+            sts_region: None,
+            chain: Vec::new()
         }));
 
         assert!(organization.profiles.contains(&Profile {
@@ -356,10 +494,36 @@ baz = {{ application = "baz", role = "baz_role" }}
             application_name: String::from("baz"),
             account: None,
             roles: vec![String::from("baz_role")],
-            duration_seconds: Some(300)
+            duration_seconds: Some(300),
+            sts_region: None,
+            chain: Vec::new()
         }));
     }
 
+    #[test]
+    fn parse_organization_base_url() {
+        let tempdir = tempfile::tempdir().unwrap();
+
+        let filepath = tempdir.path().join("mock_org.toml");
+        let mut file = File::create(filepath.clone()).unwrap();
+        write!(file, "username = \"mock_user\"\n[profiles]").unwrap();
+
+        let organization = Organization::try_from(filepath.as_path()).unwrap();
+        assert_eq!(organization.base_url.as_str(), "https://mock_org.okta.com/");
+
+        let filepath = tempdir.path().join("vanity.toml");
+        let mut file = File::create(filepath.clone()).unwrap();
+        write!(
+            file,
+            "base_url = \"https://login.example.com\"\nusername = \"mock_user\"\n[profiles]"
+        )
+        .unwrap();
+
+        let organization = Organization::try_from(filepath.as_path()).unwrap();
+        assert_eq!(organization.name, "vanity");
+        assert_eq!(organization.base_url.as_str(), "https://login.example.com/");
+    }
+
     #[test]
     fn parse_organization_with_single_role() {
         let tempdir = tempfile::tempdir().unwrap();
@@ -390,7 +554,9 @@ foo = "foo"
             application_name: String::from("foo"),
             account: None,
             roles: vec![String::from("my_role")],
-            duration_seconds: Some(300)
+            duration_seconds: Some(300),
+            sts_region: None,
+            chain: Vec::new()
         }));
     }
 
@@ -467,6 +633,74 @@ foo = "foo"
         assert_eq!(organization.profiles[0].duration_seconds, None);
     }
 
+    #[test]
+    fn parse_organization_with_chained_profiles() {
+        let tempdir = tempfile::tempdir().unwrap();
+
+        let filepath = tempdir.path().join("mock_org.toml");
+        let mut file = File::create(filepath.clone()).unwrap();
+
+        write!(
+            file,
+            r#"
+username = "mock_user"
+role = "hub_role"
+[profiles]
+hub = "hub"
+spoke = {{ source_profile = "hub", role_arn = "arn:aws:iam::111111111111:role/spoke", external_id = "abc" }}
+leaf = {{ source_profile = "spoke", role_arn = "arn:aws:iam::222222222222:role/leaf", session_name = "me" }}
+"#
+        )
+        .unwrap();
+
+        let organization = Organization::try_from(filepath.as_path()).unwrap();
+        let leaf = organization
+            .profiles
+            .iter()
+            .find(|profile| profile.name == "leaf")
+            .unwrap();
+
+        assert_eq!(leaf.application_name, "hub");
+        assert_eq!(leaf.roles, vec![String::from("hub_role")]);
+        assert_eq!(
+            leaf.chain
+                .iter()
+                .map(|role| role.role_arn.as_str())
+                .collect::<Vec<_>>(),
+            vec![
+                "arn:aws:iam::111111111111:role/spoke",
+                "arn:aws:iam::222222222222:role/leaf"
+            ]
+        );
+        assert_eq!(leaf.chain[0].external_id, Some(String::from("abc")));
+        assert_eq!(leaf.chain[1].session_name, Some(String::from("me")));
+    }
+
+    #[test]
+    fn chained_profiles_cannot_loop() {
+        let tempdir = tempfile::tempdir().unwrap();
+
+        let filepath = tempdir.path().join("mock_org.toml");
+        let mut file = File::create(filepath.clone()).unwrap();
+
+        write!(
+            file,
+            r#"
+username = "mock_user"
+[profiles]
+foo = {{ source_profile = "bar", role_arn = "arn:aws:iam::111111111111:role/foo" }}
+bar = {{ source_profile = "foo", role_arn = "arn:aws:iam::111111111111:role/bar" }}
+"#
+        )
+        .unwrap();
+
+        let err = Organization::try_from(filepath.as_path()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Profile foo has a loop in its source profiles (foo -> bar)"
+        );
+    }
+
     #[test]
     fn profile_cant_use_role_and_roles() {
         let tempdir = tempfile::tempdir().unwrap();
@@ -530,6 +764,69 @@ foo = "foo"
         assert_eq!(organizations.len(), 2);
     }
 
+    #[test]
+    fn finds_profile_in_single_organization() {
+        let tempdir = tempfile::tempdir().unwrap();
+
+        let mut file = File::create(tempdir.path().join("foo.toml")).unwrap();
+        write!(
+            file,
+            r#"
+username = "foo_user"
+role = "my_role"
+[profiles]
+prod = "Production"
+"#
+        )
+        .unwrap();
+        create_mock_toml(tempdir.path(), "bar");
+
+        let (organization, profile) = pattern_in(tempdir.path(), "*").profile("prod").unwrap();
+
+        assert_eq!(organization.name, "foo");
+        assert_eq!(profile.name, "prod");
+        assert_eq!(profile.application_name, "Production");
+    }
+
+    #[test]
+    fn missing_profile_is_an_error() {
+        let tempdir = create_mock_config_dir();
+
+        let err = pattern_in(tempdir.path(), "*").profile("prod").unwrap_err();
+
+        assert!(
+            err.to_string()
+                .starts_with("No profile named prod found in")
+        );
+    }
+
+    #[test]
+    fn ambiguous_profile_is_an_error() {
+        let tempdir = tempfile::tempdir().unwrap();
+
+        for organization_name in ["foo", "bar"] {
+            let mut file =
+                File::create(tempdir.path().join(format!("{organization_name}.toml"))).unwrap();
+            write!(
+                file,
+                r#"
+username = "user"
+role = "my_role"
+[profiles]
+prod = "Production"
+"#
+            )
+            .unwrap();
+        }
+
+        let err = pattern_in(tempdir.path(), "*").profile("prod").unwrap_err();
+
+        assert!(
+            err.to_string()
+                .starts_with("Profile prod is defined in multiple organizations")
+        );
+    }
+
     #[tokio::test]
     async fn init_without_obvious_default_role() {
         let mut client = OktaClient::new();
@@ -553,12 +850,59 @@ foo = "foo"
             ])
         });
 
-        let config = Config::from_organization(&client, String::from("test_user"))
-            .await
-            .unwrap();
+        let config =
+            Config::from_organization(&client, String::from("test_user"), &Answers::default())
+                .await
+                .unwrap();
 
         assert_eq!(config.role, None);
         assert_eq!(config.roles, None);
         assert_eq!(config.profiles.len(), 2);
     }
+
+    #[tokio::test]
+    async fn fail_fast_returns_abandoned_profiles() {
+        let mut client = OktaClient::new();
+        client
+            .expect_base_url()
+            .return_const(Url::parse("https://mock.okta.com/").unwrap());
+        client
+            .expect_app_links()
+            .returning(|_| Err(eyre!("Okta is unavailable")));
+
+        let profile = |name: &str| Profile {
+            name: name.to_string(),
+            application_name: "blah".to_string(),
+            account: None,
+            roles: vec!["mock-role".to_string()],
+            duration_seconds: None,
+            sts_region: None,
+            chain: Vec::new(),
+        };
+        let organization = Organization {
+            name: "mock".to_string(),
+            base_url: Url::parse("https://mock.okta.com/").unwrap(),
+            username: "mock_user".to_string(),
+            factors: Vec::new(),
+            pipeline: None,
+            profiles: vec![profile("foo"), profile("bar"), profile("baz")],
+        };
+
+        let results = organization
+            .into_credentials(&client, glob::Pattern::new("*").unwrap(), None, true)
+            .await
+            .collect::<Vec<_>>();
+
+        // The first failure is returned, and every other profile is returned as abandoned
+        assert_eq!(results.len(), 3);
+        assert!(matches!(results[0], (_, Some(Err(_)))));
+        assert!(results[1..].iter().all(|(_, result)| result.is_none()));
+
+        let mut names = results
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, vec!["bar", "baz", "foo"]);
+    }
 }
@@ -3,14 +3,19 @@ use mockall_double::double;
 #[double]
 use crate::okta::client::Client as OktaClient;
 use crate::{
-    aws::sts_client,
+    aws::{role::ChainedRole, sso::Client as SsoClient, sts_client, sts_client_with_credentials},
+    config::answers::Answers,
     okta::applications::{AppLink, AppLinkAccountRoleMapping},
     select,
 };
 
 use aws_credential_types::Credentials;
 use eyre::{Result, eyre};
+use futures::lock::Mutex;
+use indexmap::IndexMap;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
 use tracing::{instrument, trace};
 
 /// This is an intentionally 'loose' struct,
@@ -24,24 +29,38 @@ pub enum Config {
         account: Option<String>,
         role: Option<String>,
         duration_seconds: Option<i32>,
+        /// Region to call STS in (by default, one in the partition of the role)
+        sts_region: Option<String>,
         #[serde(skip_serializing, skip_deserializing)]
         account_id: Option<String>,
     },
+    /// A role assumed with the credentials of another profile
+    Chained {
+        source_profile: String,
+        role_arn: String,
+        external_id: Option<String>,
+        session_name: Option<String>,
+        duration_seconds: Option<i32>,
+        /// Region to call STS in (by default, one in the partition of the role)
+        sts_region: Option<String>,
+    },
 }
 
 impl Config {
     /// # Errors
     ///
     /// Will return `Err` if no profiles are found for the application.
-    /// Will return `Err` if the user fails to select a role.
+    /// Will return `Err` if the role given for the account in `answers` is not available.
+    /// Will return `Err` if the user fails to select a role, or prompting is not allowed.
     ///
     /// # Panics
     ///
     /// Panics if the `role_names` vector has exactly 1 element but `first()` returns `None`.
-    #[instrument(skip(mapping, default_roles))]
+    #[instrument(skip(mapping, default_roles, answers))]
     pub fn from_account_mapping(
         mapping: AppLinkAccountRoleMapping,
         default_roles: &[String],
+        answers: &Answers,
     ) -> Result<(String, Self)> {
         let default_roles_available = mapping
             .role_names
@@ -50,26 +69,38 @@ impl Config {
             .filter(|name| default_roles.contains(name))
             .collect::<Vec<_>>();
 
-        let role_name = match mapping.role_names.len() {
-            0 => Err(eyre!(
-                "No profiles found for application {}",
-                mapping.account_name
-            )),
-            1 => Ok(mapping.role_names.first().unwrap().clone()),
-            _ if default_roles_available.len() == 1 => {
-                Ok(default_roles_available.first().unwrap().clone())
-            }
-            _ if default_roles_available.len() > 1 => Ok(select(
-                default_roles_available.clone(),
-                format!("Choose Role for {}", mapping.account_name),
-                std::clone::Clone::clone,
-            )?),
-            _ => Ok(select(
-                mapping.role_names.clone(),
-                format!("Choose Role for {}", mapping.account_name),
-                std::clone::Clone::clone,
-            )?),
-        }?;
+        let role_name = if let Some(role_name) =
+            answers.available_role_for(&mapping.account_name, &mapping.role_names)?
+        {
+            role_name
+        } else {
+            match mapping.role_names.len() {
+                0 => Err(eyre!(
+                    "No profiles found for application {}",
+                    mapping.account_name
+                )),
+                1 => Ok(mapping.role_names.first().unwrap().clone()),
+                _ if default_roles_available.len() == 1 => {
+                    Ok(default_roles_available.first().unwrap().clone())
+                }
+                _ if default_roles_available.len() > 1 => {
+                    answers.allow_prompt(&format!("A role for {}", mapping.account_name))?;
+                    Ok(select(
+                        default_roles_available.clone(),
+                        format!("Choose Role for {}", mapping.account_name),
+                        std::clone::Clone::clone,
+                    )?)
+                }
+                _ => {
+                    answers.allow_prompt(&format!("A role for {}", mapping.account_name))?;
+                    Ok(select(
+                        mapping.role_names.clone(),
+                        format!("Choose Role for {}", mapping.account_name),
+                        std::clone::Clone::clone,
+                    )?)
+                }
+            }?
+        };
         let profile_config = if default_roles_available.contains(&role_name)
             && default_roles_available.len() == 1
             && mapping.account_id.is_none()
@@ -86,6 +117,7 @@ impl Config {
                 account: Some(mapping.account_name.clone()),
                 role: None,
                 duration_seconds: None,
+                sts_region: None,
                 account_id: mapping.account_id.clone(),
             }
         } else {
@@ -95,6 +127,7 @@ impl Config {
                 account: Some(mapping.account_name.clone()),
                 role: Some(role_name),
                 duration_seconds: None,
+                sts_region: None,
                 account_id: mapping.account_id.clone(),
             }
         };
@@ -103,6 +136,36 @@ impl Config {
     }
 }
 
+/// Clients for the IAM Identity Center portals of an organization's applications.
+///
+/// Getting a portal token takes a SAML exchange through Okta,
+/// so each application's token is fetched once and shared by all of its profiles.
+#[derive(Default)]
+pub struct PortalClients(Mutex<HashMap<String, Arc<SsoClient>>>);
+
+impl PortalClients {
+    /// The portal client for the Identity Center application at `app_link`,
+    /// signing in to the portal if no profile has done so yet
+    async fn get(&self, client: &OktaClient, app_link: AppLink) -> Result<Arc<SsoClient>> {
+        // Profiles of the same application wait here for the first one to sign in
+        let mut portal_clients = self.0.lock().await;
+        if let Some(sso_client) = portal_clients.get(&app_link.label) {
+            return Ok(Arc::clone(sso_client));
+        }
+
+        let label = app_link.label.clone();
+        let org_auth = client.get_org_auth_for_app_link(app_link).await?;
+        let sso_client =
+            SsoClient::new(&org_auth.org_id, &org_auth.auth_code, &org_auth.region).await?;
+
+        Ok(Arc::clone(
+            portal_clients
+                .entry(label)
+                .or_insert_with(|| Arc::new(sso_client)),
+        ))
+    }
+}
+
 /// This is a canonical representation of the Profile,
 /// with required values resolved and defaults propagated.
 #[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
@@ -112,6 +175,9 @@ pub struct Profile {
     pub account: Option<String>,
     pub roles: Vec<String>,
     pub duration_seconds: Option<i32>,
+    pub sts_region: Option<String>,
+    /// Roles to assume in turn, after federating into the source profile's role
+    pub chain: Vec<ChainedRole>,
 }
 
 impl Profile {
@@ -120,64 +186,171 @@ impl Profile {
     /// # Errors
     ///
     /// Will return `Err` if a role for the profile cannot be found
+    /// Will return `Err` if the profile is chained from another profile
     pub fn try_from_spec(
         profile_config: &Config,
         name: String,
         default_roles: Option<Vec<String>>,
         default_duration_seconds: Option<i32>,
     ) -> Result<Self> {
+        let (application_name, account, role, duration_seconds, sts_region) = match profile_config {
+            Config::Name(application) => (application, None, None, None, None),
+            Config::Detailed {
+                application,
+                account,
+                role,
+                duration_seconds,
+                sts_region,
+                ..
+            } => (
+                application,
+                account.clone(),
+                role.clone(),
+                *duration_seconds,
+                sts_region.clone(),
+            ),
+            Config::Chained { source_profile, .. } => {
+                return Err(eyre!(
+                    "Profile {} must be resolved along with its source profile {}",
+                    name,
+                    source_profile
+                ));
+            }
+        };
+
         Ok(Self {
             name,
-            application_name: match profile_config {
-                Config::Name(name) => name,
-                Config::Detailed { application, .. } => application,
-            }
-            .clone(),
-            account: match profile_config {
-                Config::Name(_) => None,
-                Config::Detailed { account, .. } => account.clone(),
-            },
-            roles: match profile_config {
-                Config::Name(_) => None,
-                Config::Detailed { role, .. } => role.clone().map(|r| vec![r]),
-            }
-            .or(default_roles)
-            .ok_or_else(|| eyre!("No role found"))?,
-            duration_seconds: match profile_config {
-                Config::Name(_) => None,
-                Config::Detailed {
-                    duration_seconds, ..
-                } => *duration_seconds,
-            }
-            .or(default_duration_seconds),
+            application_name: application_name.clone(),
+            account,
+            roles: role
+                .map(|r| vec![r])
+                .or(default_roles)
+                .ok_or_else(|| eyre!("No role found"))?,
+            duration_seconds: duration_seconds.or(default_duration_seconds),
+            sts_region,
+            chain: Vec::new(),
         })
     }
 
+    /// Parse a profile from an organization config section,
+    /// following `source_profile`s back to the profile that federates through Okta
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the profile or one of its source profiles cannot be found
+    /// Will return `Err` if the source profiles form a loop
+    /// Will return `Err` if a role for the federated profile cannot be found
+    pub fn try_from_specs(
+        profile_configs: &IndexMap<String, Config>,
+        name: &str,
+        default_roles: Option<Vec<String>>,
+        default_duration_seconds: Option<i32>,
+    ) -> Result<Self> {
+        let mut visited = vec![name];
+        let mut chain = Vec::new();
+        let mut profile_config = profile_configs
+            .get(name)
+            .ok_or_else(|| eyre!("Profile {} not found", name))?;
+
+        while let Config::Chained {
+            source_profile,
+            role_arn,
+            external_id,
+            session_name,
+            duration_seconds,
+            sts_region,
+        } = profile_config
+        {
+            if visited.contains(&source_profile.as_str()) {
+                return Err(eyre!(
+                    "Profile {} has a loop in its source profiles ({})",
+                    name,
+                    visited.join(" -> ")
+                ));
+            }
+            visited.push(source_profile);
+            chain.push(ChainedRole {
+                role_arn: role_arn.clone(),
+                external_id: external_id.clone(),
+                session_name: session_name.clone(),
+                duration_seconds: *duration_seconds,
+                sts_region: sts_region.clone(),
+            });
+
+            profile_config = profile_configs.get(source_profile).ok_or_else(|| {
+                eyre!(
+                    "Source profile {} for profile {} not found",
+                    source_profile,
+                    name
+                )
+            })?;
+        }
+        chain.reverse();
+
+        let mut profile = Self::try_from_spec(
+            profile_config,
+            name.to_string(),
+            default_roles,
+            default_duration_seconds,
+        )?;
+        profile.chain = chain;
+
+        Ok(profile)
+    }
+
+    /// Identity Center portal tokens are taken from, and added to, `portal_clients`.
+    ///
     /// # Errors
     ///
     /// Will return `Err` if the Okta application for the profile cannot be found.
     /// Will return `Err` if the SAML response or SSO credentials cannot be obtained.
     /// Will return `Err` if the role cannot be assumed.
-    #[instrument(skip(self, client), fields(organization=%client.base_url(), profile=%self.name))]
+    #[instrument(skip(self, client, portal_clients), fields(organization=%client.base_url(), profile=%self.name))]
     pub async fn into_credentials(
         self,
         client: &OktaClient,
+        portal_clients: &PortalClients,
         role_override: Option<&String>,
     ) -> Result<Credentials> {
-        let saml_app_link = client.app_links(None).await?.into_iter().find(|app_link| {
+        let app_links = client.app_links(None).await?;
+        let name = self.name.clone();
+        let chain = self.chain.clone();
+
+        let saml_app_link = app_links.iter().find(|app_link| {
             app_link.app_name == "amazon_aws" && app_link.label == self.application_name
         });
+        let sso_app_link = app_links.iter().find(|app_link| {
+            app_link.app_name == "amazon_aws_sso" && app_link.label == self.application_name
+        });
+
+        let mut credentials = if let Some(app_link) = saml_app_link {
+            self.into_saml_credentials(client, app_link.clone(), role_override)
+                .await?
+        } else if let Some(app_link) = sso_app_link {
+            self.into_sso_credentials(client, portal_clients, app_link.clone(), role_override)
+                .await?
+        } else {
+            return Err(eyre!(
+                "Could not find Okta application for profile {}",
+                name
+            ));
+        };
 
-        if let Some(app_link) = saml_app_link {
-            return self
-                .into_saml_credentials(client, app_link, role_override)
-                .await;
+        for role in chain {
+            credentials = role
+                .assume(sts_client_with_credentials(role.sts_region(), credentials))
+                .await
+                .map_err(|e| {
+                    eyre!(
+                        "Error assuming role {} for profile {} ({})",
+                        role.role_arn,
+                        name,
+                        e
+                    )
+                })?;
         }
 
-        Err(eyre!(
-            "Could not find Okta application for profile {}",
-            self.name
-        ))
+        Ok(credentials)
     }
 
     async fn into_saml_credentials(
@@ -239,8 +412,13 @@ impl Profile {
 
         trace!("Found role: {} for profile {}", saml_role.role, &self.name);
 
+        let sts_region = self
+            .sts_region
+            .as_deref()
+            .unwrap_or_else(|| saml_role.default_sts_region());
+
         let credentials = saml_role
-            .assume(sts_client(), response.saml, self.duration_seconds)
+            .assume(sts_client(sts_region), response.saml, self.duration_seconds)
             .await
             .map_err(|e| eyre!("Error assuming role for profile {} ({})", self.name, e))?;
 
@@ -248,6 +426,82 @@ impl Profile {
 
         Ok(credentials)
     }
+
+    /// Fetch credentials from the IAM Identity Center portal,
+    /// using the bearer token obtained through Okta
+    async fn into_sso_credentials(
+        self,
+        client: &OktaClient,
+        portal_clients: &PortalClients,
+        app_link: AppLink,
+        role_override: Option<&String>,
+    ) -> Result<Credentials> {
+        let account = self.account.as_deref().ok_or_else(|| {
+            eyre!(
+                "Profile {} must set an account to use Identity Center application {}",
+                self.name,
+                self.application_name
+            )
+        })?;
+
+        let sso_client = portal_clients.get(client, app_link).await?;
+        let account_id = sso_client.account_id(account).await?;
+
+        let role_name = if let Some(role_override) = role_override {
+            if !sso_client
+                .account_roles(&account_id)
+                .await?
+                .contains(role_override)
+            {
+                return Err(eyre!(
+                    "Role override, {}, does not exist for profile {}",
+                    role_override,
+                    self.name
+                ));
+            }
+            role_override.clone()
+        } else if let [role_name] = self.roles.as_slice() {
+            role_name.clone()
+        } else {
+            let roles_available = sso_client
+                .account_roles(&account_id)
+                .await?
+                .into_iter()
+                .filter(|role_name| self.roles.contains(role_name))
+                .collect::<Vec<_>>();
+
+            match roles_available.len() {
+                0 => Err(eyre!(
+                    "No roles found for profile {} in account {}",
+                    self.name,
+                    account
+                )),
+                1 => Ok(roles_available[0].clone()),
+                _ => select(
+                    roles_available,
+                    format!("Choose Role for profile {}", self.name),
+                    std::clone::Clone::clone,
+                ),
+            }?
+        };
+
+        trace!("Found role: {} for profile {}", role_name, &self.name);
+
+        let credentials = sso_client
+            .role_credentials(&account_id, &role_name)
+            .await
+            .map_err(|e| {
+                eyre!(
+                    "Error getting credentials for profile {} ({})",
+                    self.name,
+                    e
+                )
+            })?;
+
+        trace!("Credentials: {:?}", credentials);
+
+        Ok(credentials)
+    }
 }
 
 #[cfg(test)]
@@ -278,7 +532,7 @@ mod tests {
             vec!["AdminRole".to_string()],
             None,
         );
-        let (account, config) = Config::from_account_mapping(mapping, &[])?;
+        let (account, config) = Config::from_account_mapping(mapping, &[], &Answers::default())?;
 
         assert_eq!(account, "prod-account");
         match config {
@@ -292,7 +546,7 @@ mod tests {
                 assert_eq!(acc, Some("prod-account".to_string()));
                 assert_eq!(role, Some("AdminRole".to_string()));
             }
-            Config::Name(_) => panic!("Expected Detailed variant"),
+            Config::Name(_) | Config::Chained { .. } => panic!("Expected Detailed variant"),
         }
         Ok(())
     }
@@ -305,14 +559,17 @@ mod tests {
             vec!["AdminRole".to_string()],
             None,
         );
-        let (account, config) = Config::from_account_mapping(mapping, &["AdminRole".to_string()])?;
+        let (account, config) =
+            Config::from_account_mapping(mapping, &["AdminRole".to_string()], &Answers::default())?;
 
         assert_eq!(account, "prod-account");
         match config {
             Config::Name(name) => {
                 assert_eq!(name, "Production");
             }
-            Config::Detailed { .. } => panic!("Expected Name variant for single default role"),
+            Config::Detailed { .. } | Config::Chained { .. } => {
+                panic!("Expected Name variant for single default role")
+            }
         }
         Ok(())
     }
@@ -325,7 +582,8 @@ mod tests {
             vec!["AdminRole".to_string()],
             Some("123456789012".to_string()),
         );
-        let (account, config) = Config::from_account_mapping(mapping, &["AdminRole".to_string()])?;
+        let (account, config) =
+            Config::from_account_mapping(mapping, &["AdminRole".to_string()], &Answers::default())?;
 
         assert_eq!(account, "prod-account");
         match config {
@@ -341,7 +599,9 @@ mod tests {
                 assert_eq!(role, None);
                 assert_eq!(account_id, Some("123456789012".to_string()));
             }
-            Config::Name(_) => panic!("Expected Detailed variant without explicit role"),
+            Config::Name(_) | Config::Chained { .. } => {
+                panic!("Expected Detailed variant without explicit role")
+            }
         }
         Ok(())
     }
@@ -349,7 +609,7 @@ mod tests {
     #[test]
     fn test_no_roles_returns_error() {
         let mapping = create_test_mapping("prod-account", "Production", vec![], None);
-        let result = Config::from_account_mapping(mapping, &[]);
+        let result = Config::from_account_mapping(mapping, &[], &Answers::default());
 
         assert!(result.is_err());
         assert!(
@@ -368,17 +628,59 @@ mod tests {
             vec!["Role1".to_string(), "Role2".to_string()],
             Some("999888777666".to_string()),
         );
-        let (_account, config) = Config::from_account_mapping(mapping, &["Role1".to_string()])?;
+        let (_account, config) =
+            Config::from_account_mapping(mapping, &["Role1".to_string()], &Answers::default())?;
 
         match config {
             Config::Detailed { account_id, .. } => {
                 assert_eq!(account_id, Some("999888777666".to_string()));
             }
-            Config::Name(_) => panic!("Expected Detailed variant"),
+            Config::Name(_) | Config::Chained { .. } => panic!("Expected Detailed variant"),
         }
         Ok(())
     }
 
+    #[test]
+    fn test_role_for_account_is_used() -> Result<()> {
+        let mapping = create_test_mapping(
+            "prod-account",
+            "Production",
+            vec!["AdminRole".to_string(), "ReadOnlyRole".to_string()],
+            None,
+        );
+        let answers = Answers {
+            roles_for: vec!["prod-account=ReadOnlyRole".parse()?],
+            no_input: true,
+            ..Answers::default()
+        };
+        let (_account, config) = Config::from_account_mapping(mapping, &[], &answers)?;
+
+        match config {
+            Config::Detailed { role, .. } => {
+                assert_eq!(role, Some("ReadOnlyRole".to_string()));
+            }
+            Config::Name(_) | Config::Chained { .. } => panic!("Expected Detailed variant"),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_no_input_refuses_to_choose_role() {
+        let mapping = create_test_mapping(
+            "prod-account",
+            "Production",
+            vec!["AdminRole".to_string(), "ReadOnlyRole".to_string()],
+            None,
+        );
+        let answers = Answers {
+            no_input: true,
+            ..Answers::default()
+        };
+        let result = Config::from_account_mapping(mapping, &[], &answers);
+
+        assert!(result.unwrap_err().to_string().contains("--no-input"));
+    }
+
     #[test]
     fn test_account_id_none_for_federated() -> Result<()> {
         let mapping = create_test_mapping(
@@ -387,13 +689,13 @@ mod tests {
             vec!["Role1".to_string()],
             None,
         );
-        let (_account, config) = Config::from_account_mapping(mapping, &[])?;
+        let (_account, config) = Config::from_account_mapping(mapping, &[], &Answers::default())?;
 
         match config {
             Config::Detailed { account_id, .. } => {
                 assert_eq!(account_id, None);
             }
-            Config::Name(_) => panic!("Expected Detailed variant"),
+            Config::Name(_) | Config::Chained { .. } => panic!("Expected Detailed variant"),
         }
         Ok(())
     }
@@ -424,6 +726,7 @@ mod tests {
             account: Some("prod-account".to_string()),
             role: Some("AdminRole".to_string()),
             duration_seconds: Some(3600),
+            sts_region: None,
             account_id: None,
         };
         let profile = Profile::try_from_spec(&config, "my-profile".to_string(), None, None)?;
@@ -443,6 +746,7 @@ mod tests {
             account: Some("prod-account".to_string()),
             role: None,
             duration_seconds: None,
+            sts_region: None,
             account_id: None,
         };
         let profile = Profile::try_from_spec(
@@ -463,6 +767,7 @@ mod tests {
             account: Some("prod-account".to_string()),
             role: Some("AdminRole".to_string()),
             duration_seconds: None,
+            sts_region: None,
             account_id: None,
         };
         let profile = Profile::try_from_spec(&config, "my-profile".to_string(), None, Some(7200))?;
@@ -478,6 +783,7 @@ mod tests {
             account: Some("prod-account".to_string()),
             role: None,
             duration_seconds: None,
+            sts_region: None,
             account_id: None,
         };
         let result = Profile::try_from_spec(&config, "my-profile".to_string(), None, None);
@@ -493,6 +799,7 @@ mod tests {
             account: Some("prod-account".to_string()),
             role: Some("ExplicitRole".to_string()),
             duration_seconds: None,
+            sts_region: None,
             account_id: None,
         };
         let profile = Profile::try_from_spec(
@@ -513,6 +820,7 @@ mod tests {
             account: Some("prod-account".to_string()),
             role: Some("AdminRole".to_string()),
             duration_seconds: Some(1800),
+            sts_region: None,
             account_id: None,
         };
         let profile = Profile::try_from_spec(&config, "my-profile".to_string(), None, Some(7200))?;
@@ -521,6 +829,21 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_try_from_spec_sts_region() -> Result<()> {
+        let config: Config = toml::from_str(
+            r#"
+            application = "GovCloud"
+            role = "AdminRole"
+            sts_region = "us-gov-east-1"
+            "#,
+        )?;
+        let profile = Profile::try_from_spec(&config, "my-profile".to_string(), None, None)?;
+
+        assert_eq!(profile.sts_region, Some("us-gov-east-1".to_string()));
+        Ok(())
+    }
+
     #[test]
     fn test_profile_equality() {
         let profile1 = Profile {
@@ -529,6 +852,8 @@ mod tests {
             account: Some("account".to_string()),
             roles: vec!["Role1".to_string()],
             duration_seconds: Some(3600),
+            sts_region: None,
+            chain: Vec::new(),
         };
         let profile2 = Profile {
             name: "test".to_string(),
@@ -536,6 +861,8 @@ mod tests {
             account: Some("account".to_string()),
             roles: vec!["Role1".to_string()],
             duration_seconds: Some(3600),
+            sts_region: None,
+            chain: Vec::new(),
         };
         let profile3 = Profile {
             name: "different".to_string(),
@@ -543,6 +870,8 @@ mod tests {
             account: Some("account".to_string()),
             roles: vec!["Role1".to_string()],
             duration_seconds: Some(3600),
+            sts_region: None,
+            chain: Vec::new(),
         };
 
         assert_eq!(profile1, profile2);
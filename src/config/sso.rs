@@ -3,6 +3,9 @@ use std::path::Path;
 use eyre::Result;
 use serde::{Deserialize, Serialize};
 
+/// The region written to generated profiles, unless one is configured
+pub const DEFAULT_PROFILE_REGION: &str = "us-east-1";
+
 /// SSO-specific oktaws configuration, loaded from the `[sso]` section
 /// of `~/.oktaws/<org>.toml`.
 ///
@@ -13,6 +16,20 @@ pub struct SsoConfig {
     /// API does not currently list them for an account (typically JIT-gated roles).
     #[serde(default)]
     pub extra_roles: Vec<String>,
+    /// Default region written to generated profiles (`us-east-1` if not set)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+    /// Region of the IAM Identity Center instance (detected from the sign-in redirect if not set)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sso_region: Option<String>,
+}
+
+impl SsoConfig {
+    /// The default region to write to generated profiles
+    #[must_use]
+    pub fn profile_region(&self) -> &str {
+        self.region.as_deref().unwrap_or(DEFAULT_PROFILE_REGION)
+    }
 }
 
 #[derive(Deserialize)]
@@ -81,11 +98,48 @@ extra_roles = ["AdminJIT", "BreakGlassJIT"]
             load_sso_config(file.path())?,
             SsoConfig {
                 extra_roles: vec!["AdminJIT".to_string(), "BreakGlassJIT".to_string()],
+                region: None,
+                sso_region: None,
             }
         );
         Ok(())
     }
 
+    #[test]
+    fn test_load_file_with_sso_region() -> Result<()> {
+        let mut file = NamedTempFile::new()?;
+        write!(
+            file,
+            r#"
+[sso]
+region = "us-gov-west-1"
+"#
+        )?;
+        let config = load_sso_config(file.path())?;
+        assert_eq!(config.profile_region(), "us-gov-west-1");
+        assert_eq!(
+            SsoConfig::default().profile_region(),
+            DEFAULT_PROFILE_REGION
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_file_with_sso_instance_region() -> Result<()> {
+        let mut file = NamedTempFile::new()?;
+        write!(
+            file,
+            r#"
+[sso]
+sso_region = "eu-west-1"
+"#
+        )?;
+        let config = load_sso_config(file.path())?;
+        assert_eq!(config.sso_region.as_deref(), Some("eu-west-1"));
+        assert_eq!(config.profile_region(), DEFAULT_PROFILE_REGION);
+        Ok(())
+    }
+
     #[test]
     fn test_load_file_with_sso_section_no_extra_roles() -> Result<()> {
         let mut file = NamedTempFile::new()?;
@@ -121,6 +175,8 @@ extra_roles = ["AdminJIT"]
             load_sso_config(file.path())?,
             SsoConfig {
                 extra_roles: vec!["AdminJIT".to_string()],
+                region: None,
+                sso_region: None,
             }
         );
         Ok(())
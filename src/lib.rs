@@ -5,6 +5,7 @@
 pub mod aws;
 pub mod config;
 pub mod okta;
+pub mod output;
 
 use eyre::{Result, eyre};
 
@@ -2,27 +2,48 @@
 #![warn(clippy::cargo)]
 #![allow(clippy::multiple_crate_versions)]
 
+use oktaws::aws::cache::CredentialCache;
 use oktaws::aws::config::ConfigStore;
+use oktaws::aws::console::{DEFAULT_FEDERATION_ENDPOINT, destination_url, signin_url};
+use oktaws::aws::credential_process::CredentialProcessOutput;
+use oktaws::aws::env::{Shell, credential_variables, exports};
 use oktaws::aws::profile::Store as ProfileStore;
+use oktaws::aws::server::{Server, generate_token};
+use oktaws::aws::sso::Client as SsoClient;
+use oktaws::aws::sso_cache::{CachedToken, TokenCache};
+use oktaws::config::answers::{Answers, RoleFor};
+use oktaws::config::mfa::{FactorPreference, load_mfa_config};
 use oktaws::config::oktaws_home;
-use oktaws::config::organization::{Config as OrganizationConfig, Pattern as OrganizationPattern};
+use oktaws::config::organization::{
+    Config as OrganizationConfig, Organization, Pattern as OrganizationPattern, load_login_settings,
+};
+use oktaws::config::profile::{PortalClients, Profile};
 use oktaws::config::sso::load_sso_config;
-use oktaws::okta::client::Client as OktaClient;
+use oktaws::okta::client::{Client as OktaClient, LoginOptions};
+use oktaws::okta::idx::Pipeline;
+use oktaws::okta::totp::{SECRET_VAR as TOTP_SECRET_VAR, TotpSecret};
+use oktaws::output::{Event, Output, RefreshSummary, SkipReason};
 // Import sso module to make its Client impl methods available
 #[allow(unused_imports)]
 use oktaws::okta::sso;
 
 use std::convert::{TryFrom, TryInto};
+use std::ffi::OsString;
+use std::net::SocketAddr;
+use std::process::ExitCode;
+use std::time::{Duration, SystemTime};
 
+use aws_credential_types::Credentials;
 use clap::Parser;
 use clap_verbosity_flag::Verbosity;
 use color_eyre::eyre::{Result, eyre};
 use glob::Pattern;
-use tracing::instrument;
+use tracing::{error, info, instrument, warn};
 use tracing_log::AsTrace;
 use tracing_subscriber::filter::Targets;
 use tracing_subscriber::{Registry, prelude::*};
 use tracing_tree::HierarchicalLayer;
+use url::Url;
 use whoami::username;
 
 #[derive(Parser, Debug)]
@@ -31,6 +52,12 @@ struct Args {
     #[clap(flatten)]
     verbosity: Verbosity,
 
+    /// Output format (text or json). With json, results are written to stdout as
+    /// one JSON event per line, and progress is written to stderr.
+    /// Only refresh, init and init-sso support json
+    #[clap(long, global = true, default_value_t = Output::default())]
+    output: Output,
+
     #[clap(subcommand)]
     cmd: Option<Command>,
 
@@ -48,10 +75,61 @@ enum Command {
 
     /// Generate AWS SSO configuration in ~/.aws/config
     InitSso(InitSsoArgs),
+
+    /// Print credentials for a profile in the AWS `credential_process` format
+    CredentialProcess(ProfileArgs),
+
+    /// Print shell commands that export credentials for a profile
+    Env(EnvArgs),
+
+    /// Run a command with credentials for a profile in its environment
+    Exec(ExecArgs),
+
+    /// Serve credentials for a profile to containers, over HTTP
+    Serve(ServeArgs),
+
+    /// Keep credentials fresh in the background, using stored Okta sessions
+    Daemon(DaemonArgs),
+
+    /// Generate a URL that signs in to the AWS console as a profile
+    Console(ConsoleArgs),
+
+    /// End stored Okta sessions
+    Logout(LogoutArgs),
+
+    /// Store a TOTP secret, used to answer TOTP challenges without prompting
+    TotpSecret(TotpSecretArgs),
+}
+
+impl Command {
+    /// The name of this command, if it cannot write its results as JSON events
+    const fn without_json_output(&self) -> Option<&'static str> {
+        match self {
+            Self::Refresh(_) | Self::Init(_) | Self::InitSso(_) => None,
+            Self::CredentialProcess(_) => Some("credential-process"),
+            Self::Env(_) => Some("env"),
+            Self::Exec(_) => Some("exec"),
+            Self::Serve(_) => Some("serve"),
+            Self::Daemon(_) => Some("daemon"),
+            Self::Console(_) => Some("console"),
+            Self::Logout(_) => Some("logout"),
+            Self::TotpSecret(_) => Some("totp-secret"),
+        }
+    }
+}
+
+/// Reject `--output json` for commands that would silently ignore it
+fn check_output(command: Option<&Command>, output: Output) -> Result<()> {
+    if output == Output::Json
+        && let Some(name) = command.and_then(Command::without_json_output)
+    {
+        return Err(eyre!("{name} does not support --output json"));
+    }
+    Ok(())
 }
 
 #[tokio::main]
-async fn main() -> Result<()> {
+async fn main() -> Result<ExitCode> {
     color_eyre::install()?;
 
     let args = Args::parse();
@@ -64,12 +142,26 @@ async fn main() -> Result<()> {
         .with(HierarchicalLayer::new(2).with_targets(true));
     tracing::subscriber::set_global_default(subscriber)?;
 
+    check_output(args.cmd.as_ref(), args.output)?;
+
     match args.cmd {
-        Some(Command::Refresh(args)) => refresh(args).await,
-        Some(Command::Init(args)) => init(args.try_into()?).await,
-        Some(Command::InitSso(args)) => init_sso(args.try_into()?).await,
-        None => refresh(args.default).await,
-    }
+        Some(Command::Refresh(refresh_args)) => return refresh(refresh_args, args.output).await,
+        Some(Command::Init(init_args)) => init(init_args.try_into()?, args.output).await,
+        Some(Command::InitSso(init_sso_args)) => {
+            init_sso(init_sso_args.try_into()?, args.output).await
+        }
+        Some(Command::CredentialProcess(args)) => credential_process(args).await,
+        Some(Command::Env(args)) => env(args).await,
+        Some(Command::Exec(args)) => exec(args).await,
+        Some(Command::Serve(args)) => serve(args).await,
+        Some(Command::Daemon(args)) => daemon(args).await,
+        Some(Command::Console(args)) => console(args).await,
+        Some(Command::Logout(args)) => logout(args).await,
+        Some(Command::TotpSecret(args)) => totp_secret(&args),
+        None => return refresh(args.default, args.output).await,
+    }?;
+
+    Ok(ExitCode::SUCCESS)
 }
 
 #[derive(Parser, Debug)]
@@ -89,16 +181,31 @@ struct RefreshArgs {
     /// Forces new credentials
     #[clap(short, long = "force-new")]
     pub force_new: bool,
+
+    /// Refresh profiles whose cached credentials expire within this many seconds
+    #[clap(long = "refresh-threshold", default_value_t = 600)]
+    pub refresh_threshold: u64,
+
+    /// MFA factor to use, as TYPE[:PROVIDER[:DEVICE]] (may be repeated, in order of preference)
+    #[clap(long = "factor")]
+    pub factors: Vec<FactorPreference>,
+
+    /// Stop at the first profile that fails to refresh
+    #[clap(long = "fail-fast")]
+    pub fail_fast: bool,
 }
 
 #[instrument(skip_all, fields(organizations=%args.organizations,profiles=%args.profiles))]
-async fn refresh(args: RefreshArgs) -> Result<()> {
-    // Set up a store for AWS profiles
-    let mut aws_credentials = ProfileStore::load(None)?;
-
+async fn refresh(args: RefreshArgs, output: Output) -> Result<ExitCode> {
     // Load AWS config to check for SSO profile conflicts
     let aws_config = ConfigStore::load(None)?;
 
+    // Load the expiry times of previously written credentials
+    let cache = CredentialCache::load(None)?;
+
+    // Credentials are only written once they have all been fetched
+    let mut refreshed = Vec::new();
+
     let organizations = args.organizations.organizations()?;
 
     if organizations.is_empty() {
@@ -108,48 +215,671 @@ async fn refresh(args: RefreshArgs) -> Result<()> {
         ));
     }
 
-    for organization in organizations {
-        // Collect profiles that conflict with SSO profiles before fetching credentials
-        let mut conflicting_profiles = Vec::new();
-        for profile in &organization.profiles {
-            if args.profiles.matches(&profile.name) && aws_config.is_sso_profile(&profile.name) {
-                conflicting_profiles.push(profile.name.clone());
+    let mut summary = RefreshSummary::default();
+
+    for mut organization in organizations {
+        // Organizations after a failure are not refreshed with --fail-fast, but their profiles are still reported
+        if args.fail_fast && !summary.failed.is_empty() {
+            let organization_name = organization.name.clone();
+            for profile in organization.into_profiles(args.profiles.clone()) {
+                summary.report(
+                    output,
+                    &Event::ProfileSkipped {
+                        organization: organization_name.clone(),
+                        profile: profile.name,
+                        reason: SkipReason::FailFast,
+                    },
+                )?;
             }
+            continue;
         }
 
-        // Warn about conflicts but continue with non-conflicting profiles
-        for profile_name in &conflicting_profiles {
-            eprintln!(
-                "Warning: Skipping profile '{profile_name}' - already exists as an SSO profile in ~/.aws/config. \
-                Please rename one of the profiles to avoid the conflict."
-            );
+        skip_profiles(
+            &mut organization,
+            &args,
+            &aws_config,
+            &cache,
+            output,
+            &mut summary,
+        )?;
+
+        // Avoid logging in to Okta if there is nothing to refresh
+        if organization.profiles.is_empty() {
+            continue;
         }
 
-        let okta_client = OktaClient::new(
+        let okta_client = match OktaClient::new(
             organization.name.clone(),
             organization.username.clone(),
-            args.force_new,
+            organization.login_options(args.force_new, &args.factors),
         )
-        .await?;
+        .await
+        {
+            Ok(okta_client) => okta_client,
+            Err(e) => {
+                // None of this organization's profiles can be refreshed without logging in
+                error!("Failed to log in to {} ({e})", organization.name);
+                for profile in &organization.profiles {
+                    summary.report(
+                        output,
+                        &Event::ProfileFailed {
+                            organization: organization.name.clone(),
+                            profile: profile.name.clone(),
+                            error: format!("Failed to log in ({e})"),
+                        },
+                    )?;
+                }
+                continue;
+            }
+        };
 
+        let organization_name = organization.name.clone();
         let credentials_map = organization
             .into_credentials(
                 &okta_client,
                 args.profiles.clone(),
                 args.role_override.as_ref(),
+                args.fail_fast,
             )
             .await;
 
         for (name, creds) in credentials_map {
-            // Skip profiles that have SSO conflicts
-            if conflicting_profiles.contains(&name) {
+            let event = match creds {
+                Some(Ok(creds)) => {
+                    let event = Event::profile_refreshed(&organization_name, &name, &creds)?;
+                    refreshed.push((name, creds));
+                    event
+                }
+                Some(Err(e)) => {
+                    error!("{e}");
+                    Event::ProfileFailed {
+                        organization: organization_name.clone(),
+                        profile: name,
+                        error: e.to_string(),
+                    }
+                }
+                None => Event::ProfileSkipped {
+                    organization: organization_name.clone(),
+                    profile: name,
+                    reason: SkipReason::FailFast,
+                },
+            };
+            summary.report(output, &event)?;
+        }
+    }
+
+    save_credentials(refreshed)?;
+
+    output.message(&summary.to_string());
+    let exit_code = summary.exit_code();
+    output.event(&Event::RefreshFinished(summary))?;
+
+    Ok(ExitCode::from(exit_code))
+}
+
+/// Write refreshed credentials to ~/.aws/credentials, and record when they expire.
+///
+/// The store is locked only while it is written, so that other oktaws processes are not kept waiting
+/// on logins. It is reloaded under the lock, to keep credentials they wrote in the meantime.
+fn save_credentials(refreshed: Vec<(String, Credentials)>) -> Result<()> {
+    if refreshed.is_empty() {
+        return Ok(());
+    }
+
+    let _lock = ProfileStore::lock(None)?;
+    let mut aws_credentials = ProfileStore::load(None)?;
+    let mut cache = CredentialCache::load(None)?;
+    for (name, creds) in refreshed {
+        aws_credentials.upsert_credential(&name, &creds)?;
+        cache.record(&name, &creds);
+    }
+    aws_credentials.save()?;
+    cache.save()
+}
+
+/// Remove the profiles that will not be refreshed from `organization`, reporting why they were skipped
+fn skip_profiles(
+    organization: &mut Organization,
+    args: &RefreshArgs,
+    aws_config: &ConfigStore,
+    cache: &CredentialCache,
+    output: Output,
+    summary: &mut RefreshSummary,
+) -> Result<()> {
+    // Reuse credentials that are still valid, unless new ones (or a different role) were asked for
+    let reuse_credentials = !args.force_new && args.role_override.is_none();
+    let refresh_threshold = Duration::from_secs(args.refresh_threshold);
+
+    let mut profiles = Vec::new();
+    for profile in std::mem::take(&mut organization.profiles) {
+        if !args.profiles.matches(&profile.name) {
+            continue;
+        }
+
+        let reason = if is_sso_conflict(aws_config, &profile)? {
+            // Warn about conflicts but continue with non-conflicting profiles
+            output.message(&format!(
+                "Warning: Skipping profile '{}' - already exists as an SSO profile for another application in ~/.aws/config. \
+                Please rename one of the profiles to avoid the conflict.",
+                profile.name
+            ));
+            SkipReason::SsoConflict
+        } else if reuse_credentials && cache.is_fresh(&profile.name, refresh_threshold) {
+            info!(
+                "Skipping profile '{}' - cached credentials are still valid",
+                profile.name
+            );
+            SkipReason::Fresh
+        } else {
+            profiles.push(with_sso_profile_settings(aws_config, profile)?);
+            continue;
+        };
+
+        summary.report(
+            output,
+            &Event::ProfileSkipped {
+                organization: organization.name.clone(),
+                profile: profile.name,
+                reason,
+            },
+        )?;
+    }
+    organization.profiles = profiles;
+
+    Ok(())
+}
+
+/// Whether `profile` has the same name as an SSO profile in ~/.aws/config that it cannot be refreshed for.
+///
+/// Profiles that `init-sso` wrote for the profile's own Identity Center application are not a conflict,
+/// as refresh fetches their credentials through the same portal.
+fn is_sso_conflict(aws_config: &ConfigStore, profile: &Profile) -> Result<bool> {
+    Ok(aws_config
+        .get_profile_session(&profile.name)?
+        .is_some_and(|session| session != sanitize_session_name(&profile.application_name)))
+}
+
+/// Fill in the account and role of `profile` from the SSO profile `init-sso` wrote for it,
+/// if the oktaws config does not set an account.
+fn with_sso_profile_settings(aws_config: &ConfigStore, mut profile: Profile) -> Result<Profile> {
+    if profile.account.is_none()
+        && let Some(account_id) = aws_config.get_profile_account_id(&profile.name)?
+    {
+        profile.account = Some(account_id);
+        if let Some(role) = aws_config.get_profile_role(&profile.name)? {
+            profile.roles = vec![role];
+        }
+    }
+    Ok(profile)
+}
+
+#[derive(Parser, Clone, Debug)]
+struct ProfileArgs {
+    /// Okta organizations to search for the profile
+    #[clap(short, long, default_value = "*")]
+    pub organizations: OrganizationPattern,
+
+    /// Profile to fetch credentials for
+    pub profile: String,
+
+    /// Role to override toml file with
+    #[clap(short, long = "role-override")]
+    pub role_override: Option<String>,
+
+    /// Forces new credentials
+    #[clap(short, long = "force-new")]
+    pub force_new: bool,
+
+    /// MFA factor to use, as TYPE[:PROVIDER[:DEVICE]] (may be repeated, in order of preference)
+    #[clap(long = "factor")]
+    pub factors: Vec<FactorPreference>,
+}
+
+/// Fetch credentials for a single profile, without writing them to ~/.aws/credentials
+#[instrument(skip_all, fields(organizations=%args.organizations,profile=%args.profile))]
+async fn profile_credentials(args: &ProfileArgs) -> Result<Credentials> {
+    let (organization, profile) = args.organizations.profile(&args.profile)?;
+    let profile = with_sso_profile_settings(&ConfigStore::load(None)?, profile)?;
+
+    let okta_client = OktaClient::new(
+        organization.name.clone(),
+        organization.username.clone(),
+        organization.login_options(args.force_new, &args.factors),
+    )
+    .await?;
+
+    profile
+        .into_credentials(
+            &okta_client,
+            &PortalClients::default(),
+            args.role_override.as_ref(),
+        )
+        .await
+}
+
+/// Print credentials for a profile as the JSON expected by `credential_process`
+async fn credential_process(args: ProfileArgs) -> Result<()> {
+    let credentials = profile_credentials(&args).await?;
+    let output = CredentialProcessOutput::try_from(&credentials)?;
+
+    println!("{}", serde_json::to_string_pretty(&output)?);
+
+    Ok(())
+}
+
+#[derive(Parser, Debug)]
+struct EnvArgs {
+    #[clap(flatten)]
+    pub profile: ProfileArgs,
+
+    /// Shell to print commands for (bash, zsh, fish or powershell)
+    #[clap(long, default_value_t = Shell::default())]
+    pub shell: Shell,
+}
+
+/// Print credentials for a profile as shell commands, for use with `eval`
+async fn env(args: EnvArgs) -> Result<()> {
+    let credentials = profile_credentials(&args.profile).await?;
+
+    println!("{}", exports(&credentials, args.shell)?);
+
+    Ok(())
+}
+
+/// Set in the environment of commands run by `oktaws exec`, to the name of the profile
+const EXEC_MARKER: &str = "OKTAWS_PROFILE";
+
+#[derive(Parser, Debug)]
+struct ExecArgs {
+    #[clap(flatten)]
+    pub profile: ProfileArgs,
+
+    /// Region to set as AWS_REGION (by default, the region already set, or the profile's region in ~/.aws/config)
+    #[clap(long)]
+    pub region: Option<String>,
+
+    /// Command to run, and its arguments
+    #[clap(last = true, required = true)]
+    pub command: Vec<OsString>,
+}
+
+/// Run a command with credentials in its environment.
+/// The credentials are only ever held in memory, and are not written to disk.
+async fn exec(args: ExecArgs) -> Result<()> {
+    if let Ok(profile) = std::env::var(EXEC_MARKER) {
+        return Err(eyre!(
+            "Already running in oktaws exec for profile {profile}, refusing to nest"
+        ));
+    }
+
+    let region = match args
+        .region
+        .clone()
+        .or_else(|| std::env::var("AWS_REGION").ok())
+        .or_else(|| std::env::var("AWS_DEFAULT_REGION").ok())
+    {
+        Some(region) => Some(region),
+        None => ConfigStore::load(None)?.get_profile_region(&args.profile.profile)?,
+    };
+
+    let credentials = profile_credentials(&args.profile).await?;
+
+    let (program, program_args) = args
+        .command
+        .split_first()
+        .ok_or_else(|| eyre!("No command given"))?;
+
+    let mut command = std::process::Command::new(program);
+    command
+        .args(program_args)
+        .env(EXEC_MARKER, &args.profile.profile)
+        // Tools may prefer a named profile over the credentials in the environment
+        .env_remove("AWS_PROFILE");
+
+    for (name, value) in credential_variables(&credentials)? {
+        match value {
+            Some(value) => command.env(name, value),
+            None => command.env_remove(name),
+        };
+    }
+
+    if let Some(region) = region {
+        command
+            .env("AWS_REGION", &region)
+            .env("AWS_DEFAULT_REGION", region);
+    }
+
+    run(command)
+}
+
+/// Replace this process with the command, so that it receives signals
+/// and its exit code is seen directly by whatever started oktaws
+#[cfg(unix)]
+fn run(mut command: std::process::Command) -> Result<()> {
+    use std::os::unix::process::CommandExt;
+
+    Err(eyre!(
+        "Failed to run {:?} ({})",
+        command.get_program(),
+        command.exec()
+    ))
+}
+
+/// Run the command to completion, and exit with its exit code
+#[cfg(not(unix))]
+fn run(mut command: std::process::Command) -> Result<()> {
+    let status = command
+        .status()
+        .map_err(|e| eyre!("Failed to run {:?} ({})", command.get_program(), e))?;
+
+    std::process::exit(status.code().unwrap_or(1))
+}
+
+#[derive(Parser, Debug)]
+struct ServeArgs {
+    #[clap(flatten)]
+    pub profile: ProfileArgs,
+
+    /// Address to listen on (the AWS SDKs only accept loopback addresses over plain HTTP)
+    #[clap(long, default_value = "127.0.0.1:0")]
+    pub listen: SocketAddr,
+
+    /// Refresh credentials when they expire within this many seconds
+    #[clap(long = "refresh-threshold", default_value_t = 600)]
+    pub refresh_threshold: u64,
+}
+
+/// Serve credentials for a profile in the format of the ECS container credentials endpoint,
+/// refreshing them through the stored Okta session as they near expiry.
+async fn serve(args: ServeArgs) -> Result<()> {
+    let token = generate_token()?;
+    let server = Server::bind(
+        args.listen,
+        token.clone(),
+        Duration::from_secs(args.refresh_threshold),
+    )
+    .await?;
+
+    eprintln!(
+        "Serving credentials for {}. Give containers these environment variables:",
+        args.profile.profile
+    );
+    eprintln!(
+        "  AWS_CONTAINER_CREDENTIALS_FULL_URI={}",
+        server.full_uri()?
+    );
+    eprintln!("  AWS_CONTAINER_AUTHORIZATION_TOKEN={token}");
+
+    let mut profile_args = args.profile;
+    server
+        .run(|| {
+            let fetch_args = profile_args.clone();
+            // Only force a new Okta session for the first credentials
+            profile_args.force_new = false;
+            async move { profile_credentials(&fetch_args).await }
+        })
+        .await
+}
+
+#[derive(Parser, Debug)]
+struct ConsoleArgs {
+    #[clap(flatten)]
+    pub profile: ProfileArgs,
+
+    /// Console page to open, as a path (such as s3/home) or a full URL
+    #[clap(short, long)]
+    pub destination: Option<String>,
+
+    /// Command to open the URL with (the URL is passed as its argument), instead of printing it
+    #[clap(long)]
+    pub open: Option<OsString>,
+
+    /// Federation endpoint to exchange credentials for a sign-in token with
+    #[clap(long = "federation-endpoint", default_value = DEFAULT_FEDERATION_ENDPOINT)]
+    pub federation_endpoint: Url,
+}
+
+/// Sign in to the AWS console with a profile's credentials, through the AWS federation endpoint
+async fn console(args: ConsoleArgs) -> Result<()> {
+    let destination = destination_url(args.destination.as_deref())?;
+    let credentials = profile_credentials(&args.profile).await?;
+    let url = signin_url(&args.federation_endpoint, &credentials, &destination).await?;
+
+    match args.open {
+        None => println!("{url}"),
+        Some(open) => {
+            let status = std::process::Command::new(&open)
+                .arg(url.as_str())
+                .status()
+                .map_err(|e| eyre!("Failed to run {:?} ({})", open, e))?;
+            if !status.success() {
+                return Err(eyre!("{:?} exited with {}", open, status));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(Parser, Debug)]
+struct DaemonArgs {
+    /// Okta organizations to use
+    #[clap(short, long, default_value = "*")]
+    pub organizations: OrganizationPattern,
+
+    /// Profiles to keep fresh
+    #[clap(default_value = "*")]
+    pub profiles: Pattern,
+
+    /// Refresh profiles whose cached credentials expire within this many seconds
+    #[clap(long = "refresh-threshold", default_value_t = 600)]
+    pub refresh_threshold: u64,
+
+    /// How often to check for expiring credentials, in seconds
+    #[clap(long, default_value_t = 60)]
+    pub interval: u64,
+
+    /// Command to run when an organization needs logging in to again (the message is passed as its argument)
+    #[clap(long)]
+    pub notify: Option<OsString>,
+}
+
+/// Refresh credentials shortly before they expire, for as long as the stored Okta sessions last.
+/// This never prompts: once logging in again is required, it notifies instead.
+#[instrument(skip_all, fields(organizations=%args.organizations,profiles=%args.profiles))]
+async fn daemon(args: DaemonArgs) -> Result<()> {
+    let refresh_threshold = Duration::from_secs(args.refresh_threshold);
+    let interval = Duration::from_secs(args.interval);
+
+    // Organizations that have already been notified about, until they are logged in to again
+    let mut notified = std::collections::HashSet::new();
+
+    loop {
+        let cache = CredentialCache::load(None)?;
+        let aws_config = ConfigStore::load(None)?;
+
+        for mut organization in args.organizations.organizations()? {
+            let mut profiles = Vec::new();
+            for profile in std::mem::take(&mut organization.profiles) {
+                if args.profiles.matches(&profile.name)
+                    && !is_sso_conflict(&aws_config, &profile)?
+                    && !cache.is_fresh(&profile.name, refresh_threshold)
+                {
+                    profiles.push(with_sso_profile_settings(&aws_config, profile)?);
+                }
+            }
+            organization.profiles = profiles;
+            if organization.profiles.is_empty() {
+                continue;
+            }
+
+            let stored_session = OktaClient::from_stored_session(
+                &organization.name,
+                &organization.username,
+                organization.login_options(false, &[]),
+            )
+            .await;
+
+            // Okta may just be unreachable for now, so try again next time
+            let stored_session = match stored_session {
+                Ok(stored_session) => stored_session,
+                Err(e) => {
+                    warn!(
+                        "Failed to check the Okta session for {} ({e})",
+                        organization.name
+                    );
+                    continue;
+                }
+            };
+
+            let Some(okta_client) = stored_session else {
+                if notified.insert(organization.name.clone()) {
+                    notify(
+                        args.notify.as_ref(),
+                        &format!(
+                            "The Okta session for {} has ended. Run `oktaws refresh -o {}` to log in again.",
+                            organization.name, organization.name
+                        ),
+                    );
+                }
                 continue;
+            };
+            notified.remove(&organization.name);
+
+            let credentials_map = organization
+                .into_credentials(&okta_client, args.profiles.clone(), None, false)
+                .await
+                .collect::<Vec<_>>();
+
+            let mut refreshed = Vec::new();
+            for (name, creds) in credentials_map {
+                match creds {
+                    Some(Ok(creds)) => {
+                        info!("Refreshed profile '{name}'");
+                        refreshed.push((name, creds));
+                    }
+                    Some(Err(e)) => error!("Failed to refresh profile '{name}' ({e})"),
+                    // Profiles are only abandoned with fail_fast
+                    None => {}
+                }
             }
-            aws_credentials.upsert_credential(&name, &creds)?;
+            save_credentials(refreshed)?;
+        }
+
+        tokio::time::sleep(interval).await;
+    }
+}
+
+/// Tell the user something needs their attention, on stderr and through the notify command
+fn notify(command: Option<&OsString>, message: &str) {
+    eprintln!("{message}");
+
+    if let Some(command) = command {
+        match std::process::Command::new(command).arg(message).status() {
+            Ok(status) if status.success() => {}
+            Ok(status) => warn!("Notify command exited with {status}"),
+            Err(e) => warn!("Failed to run notify command ({e})"),
+        }
+    }
+}
+
+#[derive(Parser, Debug)]
+struct LogoutArgs {
+    /// Okta organizations to log out of
+    #[clap(short, long, default_value = "*")]
+    pub organizations: OrganizationPattern,
+}
+
+#[instrument(skip_all, fields(organizations=%args.organizations))]
+async fn logout(args: LogoutArgs) -> Result<()> {
+    for organization in args.organizations.organizations()? {
+        if OktaClient::logout(
+            &organization.name,
+            &organization.username,
+            &organization.base_url,
+        )
+        .await?
+        {
+            eprintln!("Logged out of {}", organization.name);
         }
     }
 
-    aws_credentials.save()
+    Ok(())
+}
+
+#[derive(Parser, Debug)]
+struct TotpSecretArgs {
+    /// Okta organizations to store the secret for
+    #[clap(short, long, default_value = "*")]
+    pub organizations: OrganizationPattern,
+
+    /// Delete the stored secret instead
+    #[clap(long)]
+    pub delete: bool,
+
+    /// Read the secret from the first line of stdin, instead of prompting for it
+    #[clap(long, conflicts_with = "delete")]
+    pub stdin: bool,
+}
+
+/// The TOTP secret given on `stdin` (if `from_stdin`) or in the environment (`env_secret`), if any
+fn given_totp_secret(
+    from_stdin: bool,
+    mut stdin: impl std::io::BufRead,
+    env_secret: Option<String>,
+) -> Result<Option<String>> {
+    if from_stdin {
+        let mut secret = String::new();
+        stdin.read_line(&mut secret)?;
+        if secret.trim().is_empty() {
+            return Err(eyre!("No TOTP secret found on stdin"));
+        }
+        return Ok(Some(secret.trim().to_string()));
+    }
+
+    Ok(env_secret.filter(|secret| !secret.is_empty()))
+}
+
+#[instrument(skip_all, fields(organizations=%args.organizations))]
+fn totp_secret(args: &TotpSecretArgs) -> Result<()> {
+    let given_secret = if args.delete {
+        None
+    } else {
+        given_totp_secret(
+            args.stdin,
+            std::io::stdin().lock(),
+            std::env::var(TOTP_SECRET_VAR).ok(),
+        )?
+    };
+
+    for organization in args.organizations.organizations()? {
+        let keyring = TotpSecret::keyring(&organization.name, &organization.username)?;
+
+        if args.delete {
+            keyring.delete_credential()?;
+            eprintln!("Deleted TOTP secret for {}", organization.name);
+            continue;
+        }
+
+        let secret = match &given_secret {
+            Some(secret) => TotpSecret::parse(secret)?,
+            None => TotpSecret::parse(
+                &dialoguer::Password::new()
+                    .with_prompt(format!("TOTP secret for {}", organization.name))
+                    .interact()?,
+            )?,
+        };
+        secret.set_cached(&keyring)?;
+
+        // Let the user check the secret against their authenticator app
+        eprintln!(
+            "Stored TOTP secret for {} (current code: {})",
+            organization.name,
+            secret.code_at(SystemTime::now())?
+        );
+    }
+
+    Ok(())
 }
 
 #[derive(Parser, Debug)]
@@ -161,9 +891,20 @@ struct InitArgs {
     #[structopt(short)]
     username: Option<String>,
 
+    /// Okta URL, for organizations not at https://ORGANIZATION.okta.com/
+    #[structopt(long = "base-url")]
+    base_url: Option<Url>,
+
     /// Forces new credentials
     #[structopt(short, long = "force-new")]
     force_new: bool,
+
+    /// MFA factor to use, as TYPE[:PROVIDER[:DEVICE]] (may be repeated, in order of preference)
+    #[structopt(long = "factor")]
+    factors: Vec<FactorPreference>,
+
+    #[clap(flatten)]
+    answers: AnswerArgs,
 }
 
 #[derive(Parser, Debug)]
@@ -175,50 +916,134 @@ struct InitSsoArgs {
     #[structopt(short)]
     username: Option<String>,
 
+    /// Okta URL, for organizations not at https://ORGANIZATION.okta.com/
+    #[structopt(long = "base-url")]
+    base_url: Option<Url>,
+
     /// Forces new credentials
     #[structopt(short, long = "force-new")]
     force_new: bool,
+
+    /// MFA factor to use, as TYPE[:PROVIDER[:DEVICE]] (may be repeated, in order of preference)
+    #[structopt(long = "factor")]
+    factors: Vec<FactorPreference>,
+
+    /// Show the changes that would be made to ~/.aws/config, without writing them
+    #[clap(long = "dry-run")]
+    dry_run: bool,
+
+    #[clap(flatten)]
+    answers: AnswerArgs,
+}
+
+/// Answers to the questions `init` and `init-sso` ask, for running them from scripts
+#[derive(Parser, Debug)]
+struct AnswerArgs {
+    /// Answer yes to confirmations, such as whether to write the config
+    #[clap(short = 'y', long)]
+    yes: bool,
+
+    /// Role to use by default for accounts that have it (may be repeated, in order of preference)
+    #[clap(long = "default-role")]
+    default_roles: Vec<String>,
+
+    /// Role to use for a particular account, as ACCOUNT=ROLE (may be repeated)
+    #[clap(long = "role-for")]
+    roles_for: Vec<RoleFor>,
+
+    /// Fail, rather than prompt, when an answer is missing
+    #[clap(long = "no-input")]
+    no_input: bool,
+}
+
+impl From<AnswerArgs> for Answers {
+    fn from(args: AnswerArgs) -> Self {
+        Self {
+            yes: args.yes,
+            default_roles: args.default_roles,
+            roles_for: args.roles_for,
+            no_input: args.no_input,
+        }
+    }
 }
 
 struct Init {
     organization: String,
     username: String,
+    base_url: Option<Url>,
     force_new: bool,
+    factors: Vec<FactorPreference>,
+    pipeline: Option<Pipeline>,
+    answers: Answers,
 }
 
 struct InitSso {
     organization: String,
     username: String,
+    base_url: Option<Url>,
     force_new: bool,
+    factors: Vec<FactorPreference>,
+    pipeline: Option<Pipeline>,
+    dry_run: bool,
+    answers: Answers,
+}
+
+/// The organization and username to log in to Okta with, prompting for any not given
+fn organization_and_username(
+    organization: Option<String>,
+    username_arg: Option<String>,
+    answers: &Answers,
+) -> Result<(String, String)> {
+    let organization = match organization {
+        Some(organization) => organization,
+        None => {
+            answers.allow_prompt("An Okta organization")?;
+            dialoguer::Input::new()
+                .with_prompt("Okta Organization Name")
+                .interact_text()?
+        }
+    };
+
+    let username = match username_arg {
+        Some(username) => username,
+        None => {
+            answers.allow_prompt(&format!("A username for {organization} (-u)"))?;
+            dialoguer::Input::<String>::new()
+                .with_prompt(format!("Username for {organization}"))
+                .default(username())
+                .interact_text()?
+        }
+    };
+
+    Ok((organization, username))
 }
 
 impl TryFrom<InitArgs> for Init {
     type Error = eyre::Error;
 
     fn try_from(args: InitArgs) -> Result<Self, Self::Error> {
-        let organization = args.organization.map_or_else(
-            || {
-                dialoguer::Input::new()
-                    .with_prompt("Okta Organization Name")
-                    .interact_text()
-            },
-            Ok,
-        )?;
+        let answers = Answers::from(args.answers);
+        let (organization, username) =
+            organization_and_username(args.organization, args.username, &answers)?;
 
-        let username = args.username.map_or_else(
-            || {
-                dialoguer::Input::<String>::new()
-                    .with_prompt(format!("Username for {organization}"))
-                    .default(username())
-                    .interact_text()
-            },
-            Ok,
-        )?;
+        let oktaws_config_path = oktaws_home()?.join(format!("{organization}.toml"));
+
+        let factors = if args.factors.is_empty() {
+            load_mfa_config(&oktaws_config_path)?.factors
+        } else {
+            args.factors
+        };
+
+        let settings = load_login_settings(&oktaws_config_path)?;
 
         Ok(Self {
             organization,
             username,
+            base_url: args.base_url.or(settings.base_url),
             force_new: args.force_new,
+            factors,
+            pipeline: settings.pipeline,
+            answers,
         })
     }
 }
@@ -227,44 +1052,51 @@ impl TryFrom<InitSsoArgs> for InitSso {
     type Error = eyre::Error;
 
     fn try_from(args: InitSsoArgs) -> Result<Self, Self::Error> {
-        let organization = args.organization.map_or_else(
-            || {
-                dialoguer::Input::new()
-                    .with_prompt("Okta Organization Name")
-                    .interact_text()
-            },
-            Ok,
-        )?;
+        let answers = Answers::from(args.answers);
+        let (organization, username) =
+            organization_and_username(args.organization, args.username, &answers)?;
 
-        let username = args.username.map_or_else(
-            || {
-                dialoguer::Input::<String>::new()
-                    .with_prompt(format!("Username for {organization}"))
-                    .default(username())
-                    .interact_text()
-            },
-            Ok,
-        )?;
+        let oktaws_config_path = oktaws_home()?.join(format!("{organization}.toml"));
+
+        let factors = if args.factors.is_empty() {
+            load_mfa_config(&oktaws_config_path)?.factors
+        } else {
+            args.factors
+        };
+
+        let settings = load_login_settings(&oktaws_config_path)?;
 
         Ok(Self {
             organization,
             username,
+            base_url: args.base_url.or(settings.base_url),
             force_new: args.force_new,
+            factors,
+            pipeline: settings.pipeline,
+            dry_run: args.dry_run,
+            answers,
         })
     }
 }
 
 /// Output a config toml for a given organization
-async fn init(options: Init) -> Result<()> {
+async fn init(options: Init, output: Output) -> Result<()> {
     let okta_client = OktaClient::new(
         options.organization.clone(),
         options.username.clone(),
-        options.force_new,
+        LoginOptions {
+            force_prompt: options.force_new,
+            base_url: options.base_url.clone(),
+            factors: options.factors.clone(),
+            pipeline: options.pipeline,
+            no_input: options.answers.no_input,
+        },
     )
     .await?;
 
     let organization_config =
-        OrganizationConfig::from_organization(&okta_client, options.username).await?;
+        OrganizationConfig::from_organization(&okta_client, options.username, &options.answers)
+            .await?;
 
     // Filter to only federated profiles (exclude Identity Center profiles which have account_id)
     // This command only writes federated profiles to the oktaws config file
@@ -272,41 +1104,52 @@ async fn init(options: Init) -> Result<()> {
         .profiles
         .into_iter()
         .filter(|(_, profile_config)| match &profile_config {
-            oktaws::config::profile::Config::Name(_) => true,
+            oktaws::config::profile::Config::Name(_)
+            | oktaws::config::profile::Config::Chained { .. } => true,
             oktaws::config::profile::Config::Detailed { account_id, .. } => account_id.is_none(),
         })
         .collect();
 
-    // Create oktaws config with only federated profiles
+    let oktaws_home = oktaws_home()?;
+    let oktaws_config_path = oktaws_home.join(format!("{}.toml", options.organization));
+
+    // Create oktaws config with only federated profiles,
+    // keeping any login settings from an existing config
     let federated_config = OrganizationConfig {
+        base_url: options.base_url,
         username: organization_config.username.clone(),
         roles: organization_config.roles.clone(),
         role: organization_config.role.clone(),
         duration_seconds: organization_config.duration_seconds,
+        pipeline: options.pipeline,
+        mfa: load_mfa_config(&oktaws_config_path)?,
         profiles: federated_profiles,
     };
 
     let org_toml = toml::to_string_pretty(&federated_config)?;
 
-    let oktaws_home = oktaws_home()?;
-    let oktaws_config_path = oktaws_home.join(format!("{}.toml", options.organization));
-
-    println!(
+    output.message(&format!(
         "Federated profiles (will be written to {}):",
         oktaws_config_path.display()
-    );
-    println!("{org_toml}");
+    ));
+    output.message(&org_toml);
 
-    let write_to_file = dialoguer::Confirm::new()
-        .with_prompt(format!("Write config to {}?", oktaws_config_path.display()))
-        .interact()?;
+    let write_to_file = options.answers.confirm(
+        &format!("Write config to {}?", oktaws_config_path.display()),
+        false,
+    )?;
 
     if write_to_file {
         std::fs::create_dir_all(oktaws_home)?;
-        std::fs::write(oktaws_config_path, org_toml)?;
+        std::fs::write(&oktaws_config_path, org_toml)?;
     }
 
-    Ok(())
+    output.event(&Event::OrganizationConfigured {
+        organization: options.organization,
+        path: oktaws_config_path,
+        profiles: federated_config.profiles.keys().cloned().collect(),
+        written: write_to_file,
+    })
 }
 
 /// Sanitize a session name to be safe for filesystem and CLI usage.
@@ -389,41 +1232,46 @@ fn determine_final_profile_name(
     }
 }
 
+/// Everything `init-sso` learns about one SSO app link
+struct SsoSessionData {
+    session_name: String,
+    display_name: String,
+    start_url: String,
+    region: String,
+    sso_profiles: indexmap::IndexMap<String, (String, Vec<String>)>,
+    token: CachedToken,
+}
+
 /// Collect session data from an SSO app link
-///
-/// Returns a tuple of (`session_name`, `display_name`, `start_url`, `region`, `sso_profiles`)
 async fn collect_sso_session_data(
     okta_client: &OktaClient,
     sso_link: oktaws::okta::applications::AppLink,
-) -> Result<
-    Option<(
-        String,
-        String,
-        String,
-        String,
-        indexmap::IndexMap<String, (String, Vec<String>)>,
-    )>,
-> {
+    output: Output,
+) -> Result<Option<SsoSessionData>> {
     let display_name = sso_link.label.clone();
     let session_name = sanitize_session_name(&display_name);
 
-    println!("\n=== Processing SSO Application: {display_name} (session: {session_name}) ===");
+    output.message(&format!(
+        "\n=== Processing SSO Application: {display_name} (session: {session_name}) ==="
+    ));
 
     // Get SSO info for this app
-    print!("Authenticating to AWS SSO... ");
-    std::io::Write::flush(&mut std::io::stdout())?;
+    output.progress("Authenticating to AWS SSO... ")?;
     let org_auth = okta_client
         .get_org_auth_for_app_link(sso_link.clone())
         .await?;
-    println!("✓");
+    output.message("✓");
 
     let start_url = format!("https://{}.awsapps.com/start", org_auth.org_id);
-    let region = "us-east-1".to_string();
+    let region = org_auth.region;
+    let token = SsoClient::new(&org_auth.org_id, &org_auth.auth_code, &region)
+        .await?
+        .cached_token(&start_url, &region)?;
 
     // Get all SSO account mappings for this app
-    println!("Fetching accounts and roles...");
+    output.message("Fetching accounts and roles...");
     let mut all_account_mappings = okta_client.get_all_account_mappings(vec![sso_link]).await?;
-    println!(
+    output.message(&format!(
         "✓ Found {} account{}",
         all_account_mappings.len(),
         if all_account_mappings.len() == 1 {
@@ -431,11 +1279,23 @@ async fn collect_sso_session_data(
         } else {
             "s"
         }
-    );
+    ));
     all_account_mappings.sort_by(|a, b| a.account_name.cmp(&b.account_name));
 
+    output.event(&Event::SsoSessionFound {
+        session: session_name.clone(),
+        display_name: display_name.clone(),
+        start_url: start_url.clone(),
+        region: region.clone(),
+        accounts: all_account_mappings.len(),
+    })?;
+
     if all_account_mappings.is_empty() {
-        println!("No accounts found for this SSO application, skipping.");
+        output.message("No accounts found for this SSO application, skipping.");
+        output.event(&Event::SsoSessionSkipped {
+            session: session_name,
+            reason: "No accounts found".to_string(),
+        })?;
         return Ok(None);
     }
 
@@ -457,27 +1317,37 @@ async fn collect_sso_session_data(
     }
 
     if sso_profiles.is_empty() {
-        println!("No SSO profiles with account IDs found for {display_name}, skipping.");
+        output.message(&format!(
+            "No SSO profiles with account IDs found for {display_name}, skipping."
+        ));
+        output.event(&Event::SsoSessionSkipped {
+            session: session_name,
+            reason: "No profiles with account IDs found".to_string(),
+        })?;
         return Ok(None);
     }
 
-    Ok(Some((
+    Ok(Some(SsoSessionData {
         session_name,
         display_name,
         start_url,
         region,
         sso_profiles,
-    )))
+        token,
+    }))
 }
 
 /// Prompt user to select a default role for an SSO session
 ///
-/// Returns `Some(role)` if user selected a default role, or `None` if user chose "None"
+/// Returns `Some(role)` if user selected a default role, or `None` if user chose "None".
+/// The first of `answers.default_roles` that any account has is used without prompting,
+/// and "None" is chosen if prompting is not allowed.
 fn prompt_for_default_role(
     display_name: &str,
     needs_selection_count: usize,
     sso_profiles: &indexmap::IndexMap<String, (String, Vec<String>)>,
     extra_roles: &[String],
+    answers: &Answers,
 ) -> Result<Option<String>> {
     // Collect all unique role names and count how many accounts have each role.
     // Exclude any role that is declared as JIT-gated via extra_roles — those must
@@ -494,6 +1364,14 @@ fn prompt_for_default_role(
         }
     }
 
+    if let Some(default_role) = answers
+        .default_roles
+        .iter()
+        .find(|role| role_counts.contains_key(*role))
+    {
+        return Ok(Some(default_role.clone()));
+    }
+
     // Sort roles by count (descending) then alphabetically
     let mut role_list: Vec<(String, usize)> = role_counts.into_iter().collect();
     role_list.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
@@ -511,6 +1389,11 @@ fn prompt_for_default_role(
                 })
                 .collect();
 
+            // Choose "None", leaving each account to its --role-for
+            if answers.no_input {
+                return Ok(None);
+            }
+
             // Add a "None" option
             let mut options = role_display;
             options.push("None (prompt for each account)".to_string());
@@ -569,16 +1452,24 @@ fn profile_needs_role_selection(available_roles: &[String], existing_role: Optio
 ///    where the existing role is present in config but no longer always-on (e.g., it
 ///    became JIT-gated) — we print a note and fall through to try the session default.
 /// 4. Otherwise → prompt interactively over `api_roles`.
+///
+/// A role given for the account with `--role-for` takes priority over all of these.
 fn compute_account_default_role(
     account_name: &str,
     api_roles: &[String],
     existing_role: Option<String>,
     session_default_role: Option<&String>,
+    answers: &Answers,
+    output: Output,
 ) -> Result<Option<String>> {
     if api_roles.is_empty() {
         return Ok(None);
     }
 
+    if let Some(role) = answers.available_role_for(account_name, api_roles)? {
+        return Ok(Some(role));
+    }
+
     if api_roles.len() == 1 {
         return Ok(Some(api_roles[0].clone()));
     }
@@ -589,9 +1480,9 @@ fn compute_account_default_role(
         }
         // Existing role is no longer always-on; fall through to try the session default
         // before re-prompting.
-        println!(
+        output.message(&format!(
             "  Note: Previously selected role '{existing}' is no longer always-on for {account_name}"
-        );
+        ));
     }
 
     if let Some(default) = session_default_role
@@ -600,6 +1491,9 @@ fn compute_account_default_role(
         return Ok(Some(default.clone()));
     }
 
+    answers.allow_prompt(&format!(
+        "A default role for {account_name} (--role-for or --default-role)"
+    ))?;
     let selection = dialoguer::Select::new()
         .with_prompt(format!(
             "Choose default (always-on) role for {account_name}"
@@ -670,9 +1564,46 @@ struct SsoSessionContext<'a> {
     display_name: &'a str,
     start_url: &'a str,
     region: &'a str,
+    profile_region: &'a str,
     sso_profiles: &'a indexmap::IndexMap<String, (String, Vec<String>)>,
     extra_roles: &'a [String],
     needs_prefix: &'a std::collections::HashSet<String>,
+    answers: &'a Answers,
+    output: Output,
+}
+
+/// Choose the default role for an SSO session, if any of its accounts need one
+fn session_default_role(
+    aws_config: &ConfigStore,
+    ctx: &SsoSessionContext<'_>,
+) -> Result<Option<String>> {
+    // Determine which accounts need a default-role prompt.
+    let mut needs_selection_profiles = Vec::new();
+    for (account_name, (_, api_roles)) in ctx.sso_profiles {
+        let true_api_roles: Vec<String> = api_roles
+            .iter()
+            .filter(|r| !ctx.extra_roles.contains(r))
+            .cloned()
+            .collect();
+        let base_profile_name =
+            determine_final_profile_name(account_name, ctx.session_name, ctx.needs_prefix);
+        let existing_role = aws_config.get_profile_role(&base_profile_name)?;
+        if profile_needs_role_selection(&true_api_roles, existing_role) {
+            needs_selection_profiles.push(account_name.clone());
+        }
+    }
+
+    if needs_selection_profiles.is_empty() {
+        Ok(None)
+    } else {
+        prompt_for_default_role(
+            ctx.display_name,
+            needs_selection_profiles.len(),
+            ctx.sso_profiles,
+            ctx.extra_roles,
+            ctx.answers,
+        )
+    }
 }
 
 /// Write all expanded profiles for one SSO session into `aws_config`.
@@ -687,41 +1618,21 @@ fn write_sso_session_profiles(
         display_name,
         start_url,
         region,
+        profile_region,
         sso_profiles,
         extra_roles,
         needs_prefix,
+        answers,
+        output,
     } = ctx;
     aws_config.upsert_sso_session(session_name, start_url, region)?;
 
-    // Determine which accounts need a default-role prompt.
-    let mut needs_selection_profiles = Vec::new();
-    for (account_name, (_, api_roles)) in *sso_profiles {
-        let true_api_roles: Vec<String> = api_roles
-            .iter()
-            .filter(|r| !extra_roles.contains(r))
-            .cloned()
-            .collect();
-        let base_profile_name =
-            determine_final_profile_name(account_name, session_name, needs_prefix);
-        let existing_role = aws_config.get_profile_role(&base_profile_name);
-        if profile_needs_role_selection(&true_api_roles, existing_role) {
-            needs_selection_profiles.push(account_name.clone());
-        }
-    }
-
-    let session_default_role = if needs_selection_profiles.is_empty() {
-        None
-    } else {
-        prompt_for_default_role(
-            display_name,
-            needs_selection_profiles.len(),
-            sso_profiles,
-            extra_roles,
-        )?
-    };
+    let session_default_role = session_default_role(aws_config, ctx)?;
 
     // Expand and write profiles.
-    println!("\nSSO profiles for {display_name} (session: {session_name}):");
+    output.message(&format!(
+        "\nSSO profiles for {display_name} (session: {session_name}):"
+    ));
     let mut count = 0;
     for (account_name, (account_id, api_roles)) in *sso_profiles {
         let true_api_roles: Vec<String> = api_roles
@@ -731,25 +1642,24 @@ fn write_sso_session_profiles(
             .collect();
         let base_profile_name =
             determine_final_profile_name(account_name, session_name, needs_prefix);
-        let existing_role = aws_config.get_profile_role(&base_profile_name);
+        let existing_role = aws_config.get_profile_role(&base_profile_name)?;
 
         let default_role = compute_account_default_role(
             account_name,
             &true_api_roles,
             existing_role,
             session_default_role.as_ref(),
+            answers,
+            *output,
         )?;
 
         if default_role.is_none() {
-            if extra_roles.is_empty() {
-                println!(
-                    "  ! {account_name}: no roles visible and no extra_roles declared; skipping"
-                );
+            let consequence = if extra_roles.is_empty() {
+                "no roles visible and no extra_roles declared; skipping"
             } else {
-                println!(
-                    "  ! {account_name}: no always-on roles visible; emitting only JIT-suffixed profiles"
-                );
-            }
+                "no always-on roles visible; emitting only JIT-suffixed profiles"
+            };
+            output.message(&format!("  ! {account_name}: {consequence}"));
         }
 
         let expanded = expand_account_profiles(
@@ -773,8 +1683,15 @@ fn write_sso_session_profiles(
                 session_name,
                 &profile.account_id,
                 &profile.role,
+                profile_region,
             )?;
-            println!("  - {}{prefixed_note}", profile.profile_name);
+            output.message(&format!("  - {}{prefixed_note}", profile.profile_name));
+            output.event(&Event::SsoProfileConfigured {
+                session: (*session_name).to_string(),
+                profile: profile.profile_name,
+                account_id: profile.account_id,
+                role: profile.role,
+            })?;
             count += 1;
         }
     }
@@ -792,13 +1709,23 @@ fn write_sso_session_profiles(
 /// 6. Preserves existing role selections when re-running
 ///
 /// Progress is displayed for long-running operations (authentication, account fetching).
-async fn init_sso(options: InitSso) -> Result<()> {
-    let okta_client = OktaClient::new(
+async fn init_sso(options: InitSso, output: Output) -> Result<()> {
+    let oktaws_config_path = oktaws_home()?.join(format!("{}.toml", options.organization));
+    let sso_config = load_sso_config(&oktaws_config_path)?;
+
+    let mut okta_client = OktaClient::new(
         options.organization.clone(),
         options.username.clone(),
-        options.force_new,
+        LoginOptions {
+            force_prompt: options.force_new,
+            base_url: options.base_url.clone(),
+            factors: options.factors.clone(),
+            pipeline: options.pipeline,
+            no_input: options.answers.no_input,
+        },
     )
     .await?;
+    okta_client.set_sso_region(sso_config.sso_region.clone());
 
     // Get app links and filter to only SSO apps
     let app_links = okta_client.app_links(None).await?;
@@ -817,7 +1744,8 @@ async fn init_sso(options: InitSso) -> Result<()> {
     // across sessions before prompting for role selections.
     let mut sessions = Vec::new();
     for sso_link in sso_links {
-        if let Some(session_data) = collect_sso_session_data(&okta_client, sso_link).await? {
+        if let Some(session_data) = collect_sso_session_data(&okta_client, sso_link, output).await?
+        {
             sessions.push(session_data);
         }
     }
@@ -829,13 +1757,13 @@ async fn init_sso(options: InitSso) -> Result<()> {
     // Count occurrences of each sanitized profile name across all sessions.
     let mut profile_name_sessions: std::collections::HashMap<String, Vec<String>> =
         std::collections::HashMap::new();
-    for (session_name, _, _, _, sso_profiles) in &sessions {
-        for profile_name in sso_profiles.keys() {
+    for session in &sessions {
+        for profile_name in session.sso_profiles.keys() {
             let sanitized = sanitize_session_name(profile_name);
             profile_name_sessions
                 .entry(sanitized)
                 .or_default()
-                .push(session_name.clone());
+                .push(session.session_name.clone());
         }
     }
 
@@ -846,24 +1774,26 @@ async fn init_sso(options: InitSso) -> Result<()> {
         .map(|(name, _)| name.clone())
         .collect();
 
-    // Load JIT-gated extra roles from the oktaws config once (same file for all sessions).
+    // JIT-gated extra roles come from the oktaws config (same file for all sessions).
     // TODO(multi-profile): consider per-session or per-account extra_roles scoping if org-wide noise becomes a problem
-    let oktaws_config_path = oktaws_home()?.join(format!("{}.toml", options.organization));
-    let extra_roles = load_sso_config(&oktaws_config_path)?.extra_roles;
+    let extra_roles = &sso_config.extra_roles;
 
     // Second pass: write sessions and profiles.
     let mut total_profiles = 0;
-    for (session_name, display_name, start_url, region, sso_profiles) in sessions {
+    for session in &sessions {
         total_profiles += write_sso_session_profiles(
             &mut aws_config,
             &SsoSessionContext {
-                session_name: &session_name,
-                display_name: &display_name,
-                start_url: &start_url,
-                region: &region,
-                sso_profiles: &sso_profiles,
-                extra_roles: &extra_roles,
+                session_name: &session.session_name,
+                display_name: &session.display_name,
+                start_url: &session.start_url,
+                region: &session.region,
+                profile_region: sso_config.profile_region(),
+                sso_profiles: &session.sso_profiles,
+                extra_roles,
                 needs_prefix: &needs_prefix,
+                answers: &options.answers,
+                output,
             },
         )?;
     }
@@ -872,26 +1802,164 @@ async fn init_sso(options: InitSso) -> Result<()> {
         return Err(eyre!("No SSO profiles were configured"));
     }
 
-    println!("\n=== Summary ===");
-    println!("Total profiles configured: {total_profiles}");
+    output.message("\n=== Summary ===");
+    output.message(&format!("Total profiles configured: {total_profiles}"));
 
-    let write_sso = dialoguer::Confirm::new()
-        .with_prompt("Write SSO configuration to ~/.aws/config?")
-        .default(true)
-        .interact()?;
+    let diff = aws_config.diff()?;
+    if diff.is_empty() {
+        output.message("\nNo changes to ~/.aws/config");
+    } else {
+        output.message(&format!("\n{diff}"));
+    }
+
+    let write_sso = !options.dry_run
+        && options
+            .answers
+            .confirm("Write SSO configuration to ~/.aws/config?", true)?;
 
     if write_sso {
         aws_config.save()?;
-        println!("\nSSO configuration written successfully!");
+
+        // Let the AWS CLI and SDKs use the sessions without `aws sso login`
+        let token_cache = TokenCache::load(None)?;
+        for session in &sessions {
+            token_cache.store(&session.session_name, &session.token)?;
+        }
+
+        output.message("\nSSO configuration written successfully!");
     }
 
-    Ok(())
+    output.event(&Event::SsoConfigured {
+        profiles: total_profiles,
+        written: write_sso,
+    })
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_given_totp_secret_from_stdin() -> Result<()> {
+        let stdin = std::io::Cursor::new("JBSW Y3DP EHPK 3PXP\nignored\n");
+        let secret = given_totp_secret(true, stdin, Some("FROM ENV".to_string()))?;
+        assert_eq!(secret, Some("JBSW Y3DP EHPK 3PXP".to_string()));
+        assert!(TotpSecret::parse(&secret.unwrap_or_default()).is_ok());
+
+        assert!(given_totp_secret(true, std::io::Cursor::new("\n"), None).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_given_totp_secret_from_env() -> Result<()> {
+        let stdin = std::io::Cursor::new("unread");
+        assert_eq!(
+            given_totp_secret(false, stdin.clone(), Some("JBSWY3DPEHPK3PXP".to_string()))?,
+            Some("JBSWY3DPEHPK3PXP".to_string())
+        );
+        assert_eq!(
+            given_totp_secret(false, stdin.clone(), Some(String::new()))?,
+            None
+        );
+        assert_eq!(given_totp_secret(false, stdin, None)?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_output_rejects_json_for_commands_without_events() -> Result<()> {
+        let args = Args::try_parse_from(["oktaws", "--output", "json", "console", "production"])?;
+        let error =
+            check_output(args.cmd.as_ref(), args.output).expect_err("console has no JSON output");
+        assert_eq!(error.to_string(), "console does not support --output json");
+
+        let args = Args::try_parse_from(["oktaws", "logout", "--output", "json"])?;
+        assert!(check_output(args.cmd.as_ref(), args.output).is_err());
+
+        let args = Args::try_parse_from(["oktaws", "console", "production"])?;
+        check_output(args.cmd.as_ref(), args.output)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_output_allows_json_for_commands_with_events() -> Result<()> {
+        for command in [
+            vec!["oktaws", "--output", "json"],
+            vec!["oktaws", "--output", "json", "refresh"],
+            vec!["oktaws", "--output", "json", "init-sso", "my-org"],
+        ] {
+            let args = Args::try_parse_from(command)?;
+            check_output(args.cmd.as_ref(), args.output)?;
+        }
+        Ok(())
+    }
+
+    fn sso_app_profile(name: &str, account: Option<&str>) -> Profile {
+        Profile {
+            name: name.to_string(),
+            application_name: "My Company AWS".to_string(),
+            account: account.map(ToString::to_string),
+            roles: vec!["ReadOnly".to_string()],
+            duration_seconds: None,
+            sts_region: None,
+            chain: Vec::new(),
+        }
+    }
+
+    fn init_sso_config() -> Result<(tempfile::NamedTempFile, ConfigStore)> {
+        let tempfile = tempfile::NamedTempFile::new()?;
+        let mut aws_config = ConfigStore::load(Some(tempfile.path()))?;
+        aws_config.upsert_sso_profile(
+            "production",
+            "my-company-aws",
+            "123456789012",
+            "AdminAccess",
+            "us-east-1",
+        )?;
+        aws_config.upsert_sso_profile(
+            "other",
+            "other-company",
+            "210987654321",
+            "AdminAccess",
+            "us-east-1",
+        )?;
+        Ok((tempfile, aws_config))
+    }
+
+    #[test]
+    fn test_is_sso_conflict_only_for_other_sessions() -> Result<()> {
+        let (_tempfile, aws_config) = init_sso_config()?;
+
+        assert!(!is_sso_conflict(
+            &aws_config,
+            &sso_app_profile("production", None)
+        )?);
+        assert!(is_sso_conflict(
+            &aws_config,
+            &sso_app_profile("other", None)
+        )?);
+        assert!(!is_sso_conflict(
+            &aws_config,
+            &sso_app_profile("federated", None)
+        )?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_with_sso_profile_settings_fills_account_and_role() -> Result<()> {
+        let (_tempfile, aws_config) = init_sso_config()?;
+
+        let profile = with_sso_profile_settings(&aws_config, sso_app_profile("production", None))?;
+        assert_eq!(profile.account, Some("123456789012".to_string()));
+        assert_eq!(profile.roles, vec!["AdminAccess".to_string()]);
+
+        // The oktaws config wins when it names an account
+        let profile =
+            with_sso_profile_settings(&aws_config, sso_app_profile("production", Some("staging")))?;
+        assert_eq!(profile.account, Some("staging".to_string()));
+        assert_eq!(profile.roles, vec!["ReadOnly".to_string()]);
+        Ok(())
+    }
+
     #[test]
     fn test_sanitize_session_name_basic() {
         assert_eq!(sanitize_session_name("My Session"), "my-session");
@@ -1062,9 +2130,15 @@ mod tests {
 
     #[test]
     fn test_compute_account_default_role_single_api_role() {
-        let result =
-            compute_account_default_role("prod-account", &["AdminAccess".to_string()], None, None)
-                .unwrap();
+        let result = compute_account_default_role(
+            "prod-account",
+            &["AdminAccess".to_string()],
+            None,
+            None,
+            &Answers::default(),
+            Output::default(),
+        )
+        .unwrap();
         assert_eq!(result, Some("AdminAccess".to_string()));
     }
 
@@ -1075,6 +2149,8 @@ mod tests {
             &["AdminAccess".to_string(), "ReadOnly".to_string()],
             Some("ReadOnly".to_string()),
             Some(&"AdminAccess".to_string()),
+            &Answers::default(),
+            Output::default(),
         )
         .unwrap();
         assert_eq!(result, Some("ReadOnly".to_string()));
@@ -1087,6 +2163,8 @@ mod tests {
             &["AdminAccess".to_string(), "ReadOnly".to_string()],
             None,
             Some(&"AdminAccess".to_string()),
+            &Answers::default(),
+            Output::default(),
         )
         .unwrap();
         assert_eq!(result, Some("AdminAccess".to_string()));
@@ -1099,11 +2177,49 @@ mod tests {
             &[],
             None,
             Some(&"AdminAccess".to_string()),
+            &Answers::default(),
+            Output::default(),
         )
         .unwrap();
         assert_eq!(result, None);
     }
 
+    #[test]
+    fn test_compute_account_default_role_role_for_account() {
+        let answers = Answers {
+            roles_for: vec!["prod-account=ReadOnly".parse().unwrap()],
+            no_input: true,
+            ..Answers::default()
+        };
+        let result = compute_account_default_role(
+            "prod-account",
+            &["AdminAccess".to_string(), "ReadOnly".to_string()],
+            Some("AdminAccess".to_string()),
+            None,
+            &answers,
+            Output::default(),
+        )
+        .unwrap();
+        assert_eq!(result, Some("ReadOnly".to_string()));
+    }
+
+    #[test]
+    fn test_compute_account_default_role_no_input_fails_instead_of_prompting() {
+        let answers = Answers {
+            no_input: true,
+            ..Answers::default()
+        };
+        let result = compute_account_default_role(
+            "prod-account",
+            &["AdminAccess".to_string(), "ReadOnly".to_string()],
+            None,
+            None,
+            &answers,
+            Output::default(),
+        );
+        assert!(result.unwrap_err().to_string().contains("--no-input"));
+    }
+
     #[test]
     fn test_compute_account_default_role_session_default_not_in_api_roles() {
         let result = compute_account_default_role(
@@ -1111,6 +2227,8 @@ mod tests {
             &["ReadOnly".to_string()],
             None,
             Some(&"AdminJIT".to_string()),
+            &Answers::default(),
+            Output::default(),
         )
         .unwrap();
         assert_eq!(result, Some("ReadOnly".to_string()));
@@ -1232,6 +2350,8 @@ mod tests {
             &["NewAdmin".to_string(), "ReadOnly".to_string()],
             Some("OldAdmin".to_string()),
             Some(&"NewAdmin".to_string()),
+            &Answers::default(),
+            Output::default(),
         )
         .unwrap();
         assert_eq!(result, Some("NewAdmin".to_string()));
@@ -1,12 +1,12 @@
 use crate::okta::client::Client;
-use crate::okta::factors::{Factor, FactorResult};
+use crate::okta::factors::{ChallengedFactor, Factor, FactorResult};
 
 use dialoguer;
 use eyre::{Result, eyre};
 use kuchiki::traits::TendrilSink;
 use regex::Regex;
 use serde::{Deserialize, Serialize};
-use tracing::{debug, info, trace};
+use tracing::{debug, info, trace, warn};
 
 #[derive(Serialize)]
 #[serde(rename_all = "camelCase")]
@@ -73,7 +73,7 @@ struct Context {
 pub struct LoginResponse {
     pub state_token: Option<String>,
     pub session_token: Option<String>,
-    status: LoginState,
+    pub status: LoginState,
     pub factor_result: Option<FactorResult>,
     #[serde(rename = "_embedded")]
     embedded: Option<LoginEmbedded>,
@@ -84,6 +84,15 @@ pub struct LoginResponse {
 pub struct LoginEmbedded {
     #[serde(default)]
     factors: Vec<Factor>,
+    factor: Option<ChallengedFactor>,
+}
+
+impl LoginResponse {
+    /// The factor that Okta has issued a challenge for, if any
+    #[must_use]
+    pub fn challenged_factor(&self) -> Option<&ChallengedFactor> {
+        self.embedded.as_ref()?.factor.as_ref()
+    }
 }
 
 #[derive(Deserialize, Debug)]
@@ -147,14 +156,18 @@ impl Client {
                     .map(|e| e.factors)
                     .ok_or_else(|| eyre!("MFA required, but no factors found"))?
                     .into_iter()
-                    .filter(Factor::is_supported)
+                    .filter(|factor| factor.is_supported(self))
                     .collect::<Vec<_>>();
 
-                let factor = match factors.len() {
-                    0 => Err(eyre!(
+                let factor = match (factors.len(), self.preferred_factor(&factors)) {
+                    (0, _) => Err(eyre!(
                         "MFA is required, but the user has no supported factors"
                     )),
-                    1 => {
+                    (_, Some(factor)) => {
+                        info!("Using preferred MFA option ({factor})");
+                        Ok(factor)
+                    }
+                    (1, None) => {
                         info!(
                             "Only one MFA option is supported ({}), using it",
                             factors[0]
@@ -162,6 +175,7 @@ impl Client {
                         Ok(&factors[0])
                     }
                     _ => {
+                        self.allow_prompt("A choice of MFA factor (--factor)")?;
                         let selection = dialoguer::Select::new()
                             .with_prompt("Choose MFA Option")
                             .items(&factors)
@@ -190,6 +204,24 @@ impl Client {
         }
     }
 
+    /// Pick a factor without prompting: the first one matching the configured preferences,
+    /// or else a TOTP factor if a TOTP secret is stored.
+    fn preferred_factor<'a>(&self, factors: &'a [Factor]) -> Option<&'a Factor> {
+        let preferred = self
+            .factor_preferences()
+            .iter()
+            .find_map(|preference| factors.iter().find(|factor| preference.matches(factor)));
+
+        if preferred.is_none() && !self.factor_preferences().is_empty() {
+            warn!("None of the preferred MFA options are available");
+        }
+
+        preferred.or_else(|| {
+            self.totp_secret()
+                .and_then(|_| factors.iter().find(|f| matches!(f, Factor::Totp { .. })))
+        })
+    }
+
     /// Check whether the page is asking for extra verification.
     /// This is a step during the okta login process that normally results from device tokens
     /// not being sent with the request.
@@ -1,4 +1,9 @@
+use crate::config::mfa::FactorPreference;
 use crate::okta::auth::LoginRequest;
+use crate::okta::default_base_url;
+use crate::okta::idx::Pipeline;
+use crate::okta::totp::TotpSecret;
+use crate::okta::webauthn::{Authenticator, CommandAuthenticator};
 
 use std::collections::HashSet;
 use std::sync::Arc;
@@ -9,7 +14,7 @@ use dialoguer::Password;
 use eyre::{Result, eyre};
 use reqwest::Response;
 use reqwest::cookie::Jar;
-use reqwest::header::{ACCEPT, HeaderValue};
+use reqwest::header::{ACCEPT, CONTENT_TYPE, HeaderValue};
 use reqwest::{Client as HttpClient, StatusCode};
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
@@ -21,6 +26,26 @@ pub struct Client {
     http: HttpClient,
     base_url: Url,
     pub cookies: Arc<Jar>,
+    webauthn: Option<Arc<dyn Authenticator>>,
+    totp_secret: Option<TotpSecret>,
+    factor_preferences: Vec<FactorPreference>,
+    sso_region: Option<String>,
+    no_input: bool,
+}
+
+/// Options controlling how a `Client` logs in
+#[derive(Clone, Debug, Default)]
+pub struct LoginOptions {
+    /// Prompt for a password (and create a new session) even if one is stored
+    pub force_prompt: bool,
+    /// Okta URL to log in to (`https://<organization>.okta.com/` if not set)
+    pub base_url: Option<Url>,
+    /// MFA factors to use without prompting, in order of preference
+    pub factors: Vec<FactorPreference>,
+    /// Authentication pipeline to log in with (detected from the organization if not set)
+    pub pipeline: Option<Pipeline>,
+    /// Fail, rather than prompt, when a password, MFA factor or pass code is needed
+    pub no_input: bool,
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -59,75 +84,248 @@ pub struct RawOktaError {
 impl Client {
     /// Create a new client for an Okta organization
     ///
+    /// A previously stored Okta session is reused if it is still active,
+    /// otherwise this logs in (prompting for credentials and MFA as needed).
+    ///
     /// # Errors
     ///
     /// Will return `Err` if a URL cannot be constructed for the organization,
     /// or if there are underlying HTTP client creation issues.
-    pub async fn new(organization: String, username: String, force_prompt: bool) -> Result<Self> {
-        let mut base_url = Url::parse(&format!("https://{organization}.okta.com/"))?;
-        base_url
-            .set_username(&username)
-            .map_err(|()| eyre!("Cannot set username for URL"))?;
+    pub async fn new(
+        organization: String,
+        username: String,
+        options: LoginOptions,
+    ) -> Result<Self> {
+        let LoginOptions {
+            force_prompt,
+            base_url,
+            factors,
+            pipeline,
+            no_input,
+        } = options;
+
+        let base_url = match base_url {
+            Some(base_url) => base_url,
+            None => default_base_url(&organization)?,
+        };
+        let mut client = Self::unauthenticated(base_url, &username)?;
+        client.set_factor_preferences(factors);
+        client.set_no_input(no_input);
 
-        let cookies = Arc::from(Jar::default());
+        let session_keyring = Self::session_keyring(&organization, &username)?;
+        if let Some(session) = Self::get_cached_session(&session_keyring) {
+            client.restore_session(&session);
 
-        let mut client = Self {
-            http: HttpClient::builder()
-                .cookie_store(true)
-                .cookie_provider(cookies.clone())
-                .build()?,
-            base_url: base_url.clone(),
-            cookies,
-        };
+            if !force_prompt && client.is_session_active().await {
+                debug!("Reusing Okta session for {}", client.base_url);
+                return Ok(client);
+            }
+        }
 
         // Visit the homepage to get a DeviceToken (DT) cookie (used for persisting MFA information).
-        client.get_response(base_url).await?;
+        client.get_response(client.base_url.clone()).await?;
+
+        // A stored TOTP secret lets TOTP factors be verified without prompting
+        client.totp_secret =
+            TotpSecret::get_cached(&TotpSecret::keyring(&organization, &username)?);
 
         let service = format!("oktaws::okta::{organization}");
         let keyring = keyring::Entry::new(&service, &username)?;
 
+        let pipeline = match pipeline {
+            Some(pipeline) => pipeline,
+            None => client.detect_pipeline().await,
+        };
+
         // get password
         let password = client.get_password(&keyring, force_prompt)?;
-        let login_request = LoginRequest::from_credentials(username.clone(), password.clone());
 
         // Do the login
-        let session_token = match client.get_session_token(&login_request).await {
-            Ok(session_token) => {
+        match client.authenticate(pipeline, &username, &password).await {
+            Ok(()) => {
                 // Save the password.
                 client.set_cached_password(&keyring, &password);
-
-                Ok(session_token)
             }
             Err(wrapped_error) => {
                 if let Some(OktaError::AuthenticationException(_)) = wrapped_error.downcast_ref() {
                     warn!("Authentication failed, re-prompting for Okta credentials");
 
                     let password = client.prompt_password()?;
-                    let login_request =
-                        LoginRequest::from_credentials(username.clone(), password.clone());
-
-                    let session_token = client.get_session_token(&login_request).await?;
+                    client.authenticate(pipeline, &username, &password).await?;
 
                     // Save the password.
                     client.set_cached_password(&keyring, &password);
-
-                    Ok(session_token)
                 } else {
-                    Err(wrapped_error)
+                    return Err(wrapped_error);
                 }
             }
-        }?;
+        }
 
-        client.new_session(session_token, &HashSet::new()).await?;
+        client.set_cached_session(&session_keyring);
 
         Ok(client)
     }
 
+    /// Create a client for an Okta organization from a stored Okta session, without logging in
+    ///
+    /// Returns `None` if no session is stored, or if it is no longer active,
+    /// meaning that logging in (with MFA) is required.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if a URL cannot be constructed for the organization,
+    /// or if there are underlying HTTP client creation issues.
+    pub async fn from_stored_session(
+        organization: &str,
+        username: &str,
+        options: LoginOptions,
+    ) -> Result<Option<Self>> {
+        let base_url = match options.base_url {
+            Some(base_url) => base_url,
+            None => default_base_url(organization)?,
+        };
+        let mut client = Self::unauthenticated(base_url, username)?;
+        client.set_factor_preferences(options.factors);
+
+        let Some(session) =
+            Self::get_cached_session(&Self::session_keyring(organization, username)?)
+        else {
+            return Ok(None);
+        };
+        client.restore_session(&session);
+        client.totp_secret = TotpSecret::get_cached(&TotpSecret::keyring(organization, username)?);
+
+        if client.is_session_active().await {
+            debug!("Reusing Okta session for {}", client.base_url);
+            Ok(Some(client))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Log in with the given credentials, leaving the client with a session
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the credentials are rejected,
+    /// or if there are any errors during MFA verification or session creation
+    pub async fn authenticate(
+        &mut self,
+        pipeline: Pipeline,
+        username: &str,
+        password: &str,
+    ) -> Result<()> {
+        match pipeline {
+            Pipeline::Classic => {
+                let login_request =
+                    LoginRequest::from_credentials(username.to_string(), password.to_string());
+                let session_token = self.get_session_token(&login_request).await?;
+
+                self.new_session(session_token, &HashSet::new()).await
+            }
+            Pipeline::Idx => self.idx_login(username, password).await,
+        }
+    }
+
+    /// Create a client for an Okta organization, without logging in
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the username cannot be set on the URL,
+    /// or if there are underlying HTTP client creation issues.
+    pub fn unauthenticated(mut base_url: Url, username: &str) -> Result<Self> {
+        // API paths are joined onto the base URL, so it must end in a slash
+        if !base_url.path().ends_with('/') {
+            base_url.set_path(&format!("{}/", base_url.path()));
+        }
+
+        base_url
+            .set_username(username)
+            .map_err(|()| eyre!("Cannot set username for URL"))?;
+
+        let cookies = Arc::from(Jar::default());
+
+        Ok(Self {
+            http: HttpClient::builder()
+                .cookie_store(true)
+                .cookie_provider(cookies.clone())
+                .build()?,
+            base_url,
+            cookies,
+            webauthn: CommandAuthenticator::from_env()
+                .map(|authenticator| Arc::new(authenticator) as Arc<dyn Authenticator>),
+            totp_secret: None,
+            factor_preferences: Vec::new(),
+            sso_region: None,
+            no_input: false,
+        })
+    }
+
     #[must_use]
     pub const fn base_url(&self) -> &Url {
         &self.base_url
     }
 
+    #[must_use]
+    pub fn webauthn_authenticator(&self) -> Option<Arc<dyn Authenticator>> {
+        self.webauthn.clone()
+    }
+
+    pub fn set_webauthn_authenticator(&mut self, authenticator: impl Authenticator + 'static) {
+        self.webauthn = Some(Arc::new(authenticator));
+    }
+
+    /// Stop using any authenticator, such as the one configured by the environment
+    #[cfg(test)]
+    pub(crate) fn clear_webauthn_authenticator(&mut self) {
+        self.webauthn = None;
+    }
+
+    #[must_use]
+    pub const fn totp_secret(&self) -> Option<&TotpSecret> {
+        self.totp_secret.as_ref()
+    }
+
+    pub fn set_totp_secret(&mut self, secret: TotpSecret) {
+        self.totp_secret = Some(secret);
+    }
+
+    #[must_use]
+    pub fn factor_preferences(&self) -> &[FactorPreference] {
+        &self.factor_preferences
+    }
+
+    pub fn set_factor_preferences(&mut self, factors: Vec<FactorPreference>) {
+        self.factor_preferences = factors;
+    }
+
+    pub const fn set_no_input(&mut self, no_input: bool) {
+        self.no_input = no_input;
+    }
+
+    /// Check that the user may be prompted for `question`
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if prompting is not allowed
+    pub fn allow_prompt(&self, question: &str) -> Result<()> {
+        if self.no_input {
+            Err(eyre!("{question} is needed, but --no-input was given"))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// The configured IAM Identity Center region, which takes precedence over the detected one
+    #[must_use]
+    pub fn sso_region(&self) -> Option<&str> {
+        self.sso_region.as_deref()
+    }
+
+    pub fn set_sso_region(&mut self, region: Option<String>) {
+        self.sso_region = region;
+    }
+
     pub fn set_session_id(&mut self, session_id: &str) {
         self.cookies
             .add_cookie_str(&format!("sid={session_id}"), &self.base_url);
@@ -205,6 +403,26 @@ impl Client {
         .await
     }
 
+    /// Given a relative path, perform a DELETE request against it (using the client's base url)
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if there are any errors performing the DELETE operation.
+    pub async fn delete(&self, path: &str) -> Result<()> {
+        let resp = self
+            .http
+            .delete(self.base_url.join(path)?)
+            .header(ACCEPT, HeaderValue::from_static("application/json"))
+            .send()
+            .await?;
+
+        if resp.status().is_success() {
+            Ok(())
+        } else {
+            Err(resp.json::<RawOktaError>().await?.into())
+        }
+    }
+
     /// Given a relative path, POST the body to it (using the client's base url)
     ///
     /// # Errors
@@ -245,7 +463,29 @@ impl Client {
         }
     }
 
+    /// Given an absolute URL, POST the body to it as the given media type,
+    /// returning the response regardless of its status.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if there are any errors sending the request.
+    pub async fn post_media_type<I>(&self, url: Url, media_type: &str, body: &I) -> Result<Response>
+    where
+        I: Serialize + Sync,
+    {
+        self.http
+            .post(url)
+            .header(ACCEPT, HeaderValue::from_str(media_type)?)
+            .header(CONTENT_TYPE, HeaderValue::from_str(media_type)?)
+            .body(serde_json::to_vec(body)?)
+            .send()
+            .await
+            .map_err(Into::into)
+    }
+
     fn prompt_password(&self) -> Result<String> {
+        self.allow_prompt(&format!("The password for {}", self.base_url))?;
+
         Password::new()
             .with_prompt(format!("Password for {}", self.base_url))
             .interact()
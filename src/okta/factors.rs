@@ -1,17 +1,23 @@
+use crate::config::mfa::FactorKind;
 use crate::okta::Links;
 use crate::okta::Links::Multi;
 use crate::okta::Links::Single;
-use crate::okta::auth::LoginResponse;
+use crate::okta::auth::{LoginResponse, LoginState};
 use crate::okta::client::Client;
 
 use std::collections::HashMap;
 use std::fmt;
-use std::thread::sleep;
-use std::time::Duration;
+use std::time::{Duration, SystemTime};
 
 use dialoguer::Password;
 use eyre::{Result, eyre};
 use serde::{Deserialize, Serialize};
+use tokio::time::sleep;
+use tracing::info;
+use url::Url;
+
+/// How often to check whether a Push has been answered
+const PUSH_POLL_INTERVAL: Duration = Duration::from_millis(100);
 
 #[derive(Deserialize, Debug, PartialEq, Eq)]
 #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
@@ -28,6 +34,16 @@ pub enum FactorResult {
     Waiting,
 }
 
+#[derive(Debug, thiserror::Error)]
+pub enum PushError {
+    #[error("Push was rejected in Okta Verify")]
+    Rejected,
+    #[error("Push timed out before it was approved in Okta Verify")]
+    Timeout,
+    #[error("Failed to verify with Push MFA ({0:?})")]
+    Failed(FactorResult),
+}
+
 #[derive(Deserialize, Debug)]
 #[serde(rename_all = "lowercase", tag = "factorType")]
 pub enum Factor {
@@ -127,6 +143,22 @@ pub enum FactorProvider {
     Yubico,
 }
 
+impl FactorProvider {
+    /// The name Okta gives the provider, which is also how it is named in MFA preferences
+    #[must_use]
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            Self::Okta => "OKTA",
+            Self::Fido => "FIDO",
+            Self::Rsa => "RSA",
+            Self::Symantec => "SYMANTEC",
+            Self::Google => "GOOGLE",
+            Self::Duo => "DUO",
+            Self::Yubico => "YUBICO",
+        }
+    }
+}
+
 #[derive(Deserialize, Debug)]
 #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
 pub enum FactorStatus {
@@ -219,18 +251,131 @@ pub enum FactorVerificationRequest {
         pass_code: String,
     },
     #[serde(rename_all = "camelCase")]
-    Token { pass_code: String },
+    Token {
+        state_token: String,
+        pass_code: String,
+    },
+    #[serde(rename_all = "camelCase")]
+    WebAuthnAssertion {
+        state_token: String,
+        client_data: String,
+        authenticator_data: String,
+        signature_data: String,
+    },
     #[serde(rename_all = "camelCase")]
     WebAuthn { state_token: String },
 }
 
+/// The factor being verified, as embedded in a challenge response
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ChallengedFactor {
+    profile: Option<ChallengedFactorProfile>,
+    #[serde(rename = "_embedded")]
+    embedded: Option<ChallengedFactorEmbedded>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ChallengedFactorProfile {
+    credential_id: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ChallengedFactorEmbedded {
+    challenge: Option<Challenge>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Challenge {
+    challenge: Option<String>,
+    correct_answer: Option<u32>,
+}
+
+impl ChallengedFactor {
+    /// The challenge to be signed by a WebAuthn authenticator
+    #[must_use]
+    pub fn challenge(&self) -> Option<&str> {
+        self.embedded
+            .as_ref()?
+            .challenge
+            .as_ref()?
+            .challenge
+            .as_deref()
+    }
+
+    /// The number to tap in Okta Verify, when number matching is enabled for Push
+    #[must_use]
+    pub fn correct_answer(&self) -> Option<u32> {
+        self.embedded.as_ref()?.challenge.as_ref()?.correct_answer
+    }
+
+    /// The (base64url-encoded) ID of the credential registered for a WebAuthn factor
+    #[must_use]
+    pub fn credential_id(&self) -> Option<&str> {
+        self.profile.as_ref()?.credential_id.as_deref()
+    }
+}
+
 impl Factor {
+    /// Whether the factor can be verified. WebAuthn needs an authenticator to be configured.
+    #[must_use]
+    pub fn is_supported(&self, client: &Client) -> bool {
+        match self {
+            Self::Web { .. } => false,
+            Self::WebAuthn { .. } => client.webauthn_authenticator().is_some(),
+            _ => true,
+        }
+    }
+
+    #[must_use]
+    pub const fn kind(&self) -> FactorKind {
+        match self {
+            Self::Push { .. } => FactorKind::Push,
+            Self::Sms { .. } => FactorKind::Sms,
+            Self::Call { .. } => FactorKind::Call,
+            Self::Token { .. } => FactorKind::Token,
+            Self::Totp { .. } => FactorKind::Totp,
+            Self::Hotp { .. } => FactorKind::Hotp,
+            Self::Question { .. } => FactorKind::Question,
+            Self::Web { .. } => FactorKind::Web,
+            Self::WebAuthn { .. } => FactorKind::WebAuthn,
+        }
+    }
+
+    #[must_use]
+    pub const fn provider_name(&self) -> &'static str {
+        match self {
+            Self::Push { provider, .. }
+            | Self::Sms { provider, .. }
+            | Self::Call { provider, .. }
+            | Self::Token { provider, .. }
+            | Self::Totp { provider, .. }
+            | Self::Hotp { provider, .. }
+            | Self::Question { provider, .. }
+            | Self::Web { provider, .. }
+            | Self::WebAuthn { provider, .. } => provider.as_str(),
+        }
+    }
+
+    /// The name of the device the factor is bound to, where Okta reports one
     #[must_use]
-    pub const fn is_supported(&self) -> bool {
-        !matches!(
-            self,
-            Self::Hotp { .. } | Self::Web { .. } | Self::WebAuthn { .. }
-        )
+    pub fn device_name(&self) -> Option<&str> {
+        match self {
+            Self::Push { profile, .. } => profile.name.as_deref(),
+            Self::Sms {
+                profile: SmsFactorProfile { phone_number, .. },
+                ..
+            }
+            | Self::Call {
+                profile: CallFactorProfile { phone_number, .. },
+                ..
+            } => Some(phone_number),
+            Self::WebAuthn { profile, .. } => profile.authenticator_name.as_deref(),
+            _ => None,
+        }
     }
 }
 
@@ -292,20 +437,7 @@ impl Client {
                     })
                     .ok_or_else(|| eyre!("No verify link found"))?;
 
-                let request = FactorVerificationRequest::Push { state_token };
-
-                // Trigger sending of Push
-                let mut response: LoginResponse = self.post_absolute(url.clone(), &request).await?;
-
-                while Some(FactorResult::Waiting) == response.factor_result {
-                    sleep(Duration::from_millis(100));
-                    response = self.post_absolute(url.clone(), &request).await?;
-                }
-
-                match response.factor_result {
-                    None | Some(FactorResult::Success) => Ok(response),
-                    Some(result) => Err(eyre!("Failed to verify with Push MFA ({:?})", result)),
-                }
+                self.verify_push(url, state_token).await
             }
             Factor::Sms { links, .. } => {
                 let url = links
@@ -328,6 +460,7 @@ impl Client {
                     .state_token
                     .ok_or_else(|| eyre!("No state token found in factor prompt response"))?;
 
+                self.allow_prompt(&format!("A code for {factor}"))?;
                 let request = FactorVerificationRequest::Sms {
                     state_token,
                     pass_code: Some(Password::new().with_prompt(factor.to_string()).interact()?),
@@ -346,17 +479,398 @@ impl Client {
 
                 url.set_query(Some("rememberDevice"));
 
+                let pass_code = if let Some(secret) = self.totp_secret() {
+                    info!("Generating code for {factor} from the stored TOTP secret");
+                    secret.code_at(SystemTime::now())?
+                } else {
+                    self.allow_prompt(&format!("A code for {factor}"))?;
+                    Password::new().with_prompt(factor.to_string()).interact()?
+                };
+
                 let request = FactorVerificationRequest::Totp {
                     state_token,
-                    pass_code: Password::new().with_prompt(factor.to_string()).interact()?,
+                    pass_code,
                 };
 
                 self.post_absolute(url, &request).await
             }
+            Factor::Token { links, .. } | Factor::Hotp { links, .. } => {
+                let url = links
+                    .get("verify")
+                    .and_then(|link| match link {
+                        Single(link) => Some(link.href.clone()),
+                        Multi(links) => links.first().map(|link| link.href.clone()),
+                    })
+                    .ok_or_else(|| eyre!("No verify link found"))?;
+
+                self.verify_pass_code(url, state_token, &factor.to_string(), |prompt| {
+                    self.allow_prompt(&format!("A code for {factor}"))?;
+                    Password::new()
+                        .with_prompt(prompt)
+                        .interact()
+                        .map_err(Into::into)
+                })
+                .await
+            }
+            Factor::WebAuthn { links, .. } => {
+                let url = links
+                    .get("verify")
+                    .and_then(|link| match link {
+                        Single(link) => Some(link.href.clone()),
+                        Multi(links) => links.first().map(|link| link.href.clone()),
+                    })
+                    .ok_or_else(|| eyre!("No verify link found"))?;
+
+                self.verify_webauthn(url, state_token).await
+            }
             _ => {
                 // TODO
                 Err(eyre!("Unsupported MFA method ({})", factor))
             }
         }
     }
+
+    /// Send a Push, and wait for it to be answered in Okta Verify.
+    ///
+    /// If number matching is enabled, the number to tap is shown while waiting.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the Push is rejected, times out, or otherwise fails
+    pub async fn verify_push(&self, url: Url, state_token: String) -> Result<LoginResponse> {
+        let request = FactorVerificationRequest::Push { state_token };
+
+        // Trigger sending of Push
+        let mut response: LoginResponse = self.post_absolute(url.clone(), &request).await?;
+        let mut shown_answer = None;
+
+        while Some(FactorResult::Waiting) == response.factor_result {
+            let correct_answer = response
+                .challenged_factor()
+                .and_then(ChallengedFactor::correct_answer);
+
+            if let Some(answer) = correct_answer.filter(|answer| shown_answer != Some(*answer)) {
+                eprintln!("Tap {answer} in Okta Verify to approve the Push");
+                shown_answer = Some(answer);
+            }
+
+            sleep(PUSH_POLL_INTERVAL).await;
+            response = self.post_absolute(url.clone(), &request).await?;
+        }
+
+        match response.factor_result {
+            None | Some(FactorResult::Success) => Ok(response),
+            Some(FactorResult::Rejected) => Err(PushError::Rejected.into()),
+            Some(FactorResult::Timeout) => Err(PushError::Timeout.into()),
+            Some(result) => Err(PushError::Failed(result).into()),
+        }
+    }
+
+    /// Verify a passcode-based factor (such as an RSA or hardware token).
+    ///
+    /// If Okta asks for the next code from the token (as RSA does after repeated failures),
+    /// the user is prompted again once it has changed.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if there are any errors while prompting or verifying
+    pub async fn verify_pass_code<P>(
+        &self,
+        url: Url,
+        state_token: String,
+        prompt_text: &str,
+        mut prompt: P,
+    ) -> Result<LoginResponse>
+    where
+        P: FnMut(&str) -> Result<String>,
+    {
+        let mut request = FactorVerificationRequest::Token {
+            state_token,
+            pass_code: prompt(prompt_text)?,
+        };
+
+        loop {
+            let response: LoginResponse = self.post_absolute(url.clone(), &request).await?;
+
+            if !matches!(response.status, LoginState::MfaChallenge)
+                || response.factor_result != Some(FactorResult::Challenge)
+            {
+                return Ok(response);
+            }
+
+            info!("Okta requires the next code from the token");
+
+            request = FactorVerificationRequest::Token {
+                state_token: response
+                    .state_token
+                    .ok_or_else(|| eyre!("No state token found in next token response"))?,
+                pass_code: prompt(&format!("{prompt_text} (next code)"))?,
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::okta::webauthn::CommandAuthenticator;
+
+    use serde_json::json;
+    use wiremock::matchers::{body_json, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn waiting_response() -> ResponseTemplate {
+        ResponseTemplate::new(200).set_body_json(json!({
+            "stateToken": "STATE_TOKEN",
+            "status": "MFA_CHALLENGE",
+            "factorResult": "WAITING",
+            "_embedded": {
+                "factor": {
+                    "id": "FACTOR_ID",
+                    "factorType": "push",
+                    "provider": "OKTA",
+                    "_embedded": {
+                        "challenge": {
+                            "correctAnswer": 42
+                        }
+                    }
+                }
+            }
+        }))
+    }
+
+    async fn push_outcome(factor_result: &str) -> Result<Result<LoginResponse>> {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/authn/factors/FACTOR_ID/verify"))
+            .respond_with(waiting_response())
+            .up_to_n_times(2)
+            .expect(2)
+            .mount(&server)
+            .await;
+        let status = if factor_result == "SUCCESS" {
+            "SUCCESS"
+        } else {
+            "MFA_CHALLENGE"
+        };
+        Mock::given(method("POST"))
+            .and(path("/api/v1/authn/factors/FACTOR_ID/verify"))
+            .and(body_json(json!({ "stateToken": "STATE_TOKEN" })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "status": status,
+                "sessionToken": "SESSION_TOKEN",
+                "factorResult": factor_result
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = Client::unauthenticated(Url::parse(&server.uri())?, "user")?;
+        Ok(client
+            .verify_push(
+                Url::parse(&server.uri())?.join("api/v1/authn/factors/FACTOR_ID/verify")?,
+                "STATE_TOKEN".to_string(),
+            )
+            .await)
+    }
+
+    #[test]
+    fn names_providers_as_okta_does() -> Result<()> {
+        for name in ["OKTA", "FIDO", "RSA", "SYMANTEC", "GOOGLE", "DUO", "YUBICO"] {
+            let provider: FactorProvider = serde_json::from_value(json!(name))?;
+            assert_eq!(provider.as_str(), name);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn supports_webauthn_with_authenticator() -> Result<()> {
+        let factor: Factor = serde_json::from_value(json!({
+            "id": "FACTOR_ID",
+            "factorType": "webauthn",
+            "provider": "FIDO",
+            "profile": {},
+            "_links": {}
+        }))?;
+
+        let mut client = Client::unauthenticated(Url::parse("https://example.okta.com")?, "user")?;
+        client.clear_webauthn_authenticator();
+        assert!(!factor.is_supported(&client));
+
+        client.set_webauthn_authenticator(CommandAuthenticator::new("true".to_string()));
+        assert!(factor.is_supported(&client));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn fails_instead_of_prompting_without_input() -> Result<()> {
+        let factor: Factor = serde_json::from_value(json!({
+            "id": "FACTOR_ID",
+            "factorType": "token:software:totp",
+            "provider": "GOOGLE",
+            "profile": {},
+            "_links": {
+                "verify": {
+                    "href": "https://example.okta.com/api/v1/authn/factors/FACTOR_ID/verify"
+                }
+            }
+        }))?;
+
+        let mut client = Client::unauthenticated(Url::parse("https://example.okta.com")?, "user")?;
+        client.set_no_input(true);
+
+        let error = client
+            .verify(&factor, "STATE_TOKEN".to_string())
+            .await
+            .expect_err("a code is needed");
+        assert_eq!(
+            error.to_string(),
+            format!("A code for {factor} is needed, but --no-input was given")
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn parses_number_challenge() -> Result<()> {
+        let response: LoginResponse = serde_json::from_value(json!({
+            "stateToken": "STATE_TOKEN",
+            "status": "MFA_CHALLENGE",
+            "factorResult": "WAITING",
+            "_embedded": {
+                "factor": {
+                    "_embedded": {
+                        "challenge": {
+                            "correctAnswer": 42
+                        }
+                    }
+                }
+            }
+        }))?;
+
+        assert_eq!(
+            response
+                .challenged_factor()
+                .and_then(ChallengedFactor::correct_answer),
+            Some(42)
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn push_waits_for_approval() -> Result<()> {
+        let response = push_outcome("SUCCESS").await??;
+        assert_eq!(response.session_token, Some("SESSION_TOKEN".to_string()));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn push_rejected() -> Result<()> {
+        let error = push_outcome("REJECTED").await?.unwrap_err();
+        assert!(matches!(error.downcast_ref(), Some(PushError::Rejected)));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn push_timed_out() -> Result<()> {
+        let error = push_outcome("TIMEOUT").await?.unwrap_err();
+        assert!(matches!(error.downcast_ref(), Some(PushError::Timeout)));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn verifies_pass_code() -> Result<()> {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/authn/factors/FACTOR_ID/verify"))
+            .and(body_json(json!({
+                "stateToken": "STATE_TOKEN",
+                "passCode": "123456"
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "status": "SUCCESS",
+                "sessionToken": "SESSION_TOKEN"
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = Client::unauthenticated(Url::parse(&server.uri())?, "user")?;
+        let response = client
+            .verify_pass_code(
+                Url::parse(&server.uri())?.join("api/v1/authn/factors/FACTOR_ID/verify")?,
+                "STATE_TOKEN".to_string(),
+                "One-time Password (from Rsa)",
+                |_| Ok("123456".to_string()),
+            )
+            .await?;
+
+        assert_eq!(response.session_token, Some("SESSION_TOKEN".to_string()));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn prompts_for_next_token_code() -> Result<()> {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/authn/factors/FACTOR_ID/verify"))
+            .and(body_json(json!({
+                "stateToken": "STATE_TOKEN",
+                "passCode": "123456"
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "stateToken": "NEXT_STATE_TOKEN",
+                "status": "MFA_CHALLENGE",
+                "factorResult": "CHALLENGE"
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/authn/factors/FACTOR_ID/verify"))
+            .and(body_json(json!({
+                "stateToken": "NEXT_STATE_TOKEN",
+                "passCode": "654321"
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "status": "SUCCESS",
+                "sessionToken": "SESSION_TOKEN"
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let mut codes = vec!["654321", "123456"];
+        let mut prompts = Vec::new();
+
+        let client = Client::unauthenticated(Url::parse(&server.uri())?, "user")?;
+        let response = client
+            .verify_pass_code(
+                Url::parse(&server.uri())?.join("api/v1/authn/factors/FACTOR_ID/verify")?,
+                "STATE_TOKEN".to_string(),
+                "One-time Password (from Rsa)",
+                |prompt| {
+                    prompts.push(prompt.to_string());
+                    codes
+                        .pop()
+                        .map(ToString::to_string)
+                        .ok_or_else(|| eyre!("Prompted too many times"))
+                },
+            )
+            .await?;
+
+        assert_eq!(response.session_token, Some("SESSION_TOKEN".to_string()));
+        assert_eq!(
+            prompts,
+            vec![
+                "One-time Password (from Rsa)",
+                "One-time Password (from Rsa) (next code)"
+            ]
+        );
+
+        Ok(())
+    }
 }
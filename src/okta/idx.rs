@@ -0,0 +1,1041 @@
+use crate::config::mfa::{FactorKind, FactorPreference};
+use crate::okta::client::{Client, OktaError};
+
+use std::fmt;
+use std::time::{Duration, SystemTime};
+
+use base64::engine::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD as b64url;
+use dialoguer::Password;
+use eyre::{Result, eyre};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value, json};
+use tokio::time::sleep;
+use tracing::{debug, info, trace, warn};
+use url::Url;
+
+/// The media type that Identity Engine requests and responses are exchanged in
+const ION_MEDIA_TYPE: &str = "application/ion+json; okta-version=1.0.0";
+
+/// How long to wait between checks of a Push, if Okta does not say
+const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(4);
+
+/// How many remediations (other than polling) to follow before giving up on a login
+const MAX_STEPS: usize = 20;
+
+/// How many times to check a Push before giving up (ten minutes, at the default interval)
+const MAX_POLLS: usize = 150;
+
+/// The remediations that can be followed, in the order they are preferred
+const REMEDIATIONS: [&str; 4] = [
+    "identify",
+    "challenge-poll",
+    "challenge-authenticator",
+    "select-authenticator-authenticate",
+];
+
+/// The ways an Okta organization can authenticate users
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Pipeline {
+    /// The classic Authentication API (`/api/v1/authn`)
+    #[default]
+    #[serde(alias = "v1")]
+    Classic,
+    /// The Okta Identity Engine interaction API (`/idp/idx`)
+    Idx,
+}
+
+#[derive(Deserialize, Debug)]
+struct OrganizationInfo {
+    pipeline: Pipeline,
+}
+
+/// A response from the Identity Engine, describing the ways the login can proceed
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct IdxResponse {
+    state_handle: Option<String>,
+    remediation: Option<IonCollection<Remediation>>,
+    current_authenticator: Option<IonObject<CurrentAuthenticator>>,
+    current_authenticator_enrollment: Option<IonObject<CurrentAuthenticator>>,
+    authenticators: Option<IonCollection<EnrolledAuthenticator>>,
+    authenticator_enrollments: Option<IonCollection<EnrolledAuthenticator>>,
+    success: Option<Remediation>,
+    messages: Option<IonCollection<Message>>,
+}
+
+#[derive(Deserialize, Debug)]
+struct IonCollection<T> {
+    value: Vec<T>,
+}
+
+#[derive(Deserialize, Debug)]
+struct IonObject<T> {
+    value: T,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Remediation {
+    name: String,
+    href: Url,
+    /// How long to wait before following this remediation, in milliseconds
+    refresh: Option<u64>,
+    #[serde(default)]
+    value: Vec<FormField>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct FormField {
+    name: String,
+    label: Option<String>,
+    value: Option<Value>,
+    form: Option<Form>,
+    #[serde(default)]
+    options: Vec<FieldOption>,
+    messages: Option<IonCollection<Message>>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Form {
+    value: Vec<FormField>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct FieldOption {
+    #[serde(default)]
+    label: String,
+    value: OptionValue,
+    relates_to: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum OptionValue {
+    Form { form: Form },
+    Value(Value),
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct CurrentAuthenticator {
+    #[serde(rename = "type")]
+    kind: Option<String>,
+    display_name: Option<String>,
+    credential_id: Option<String>,
+    contextual_data: Option<ContextualData>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ContextualData {
+    correct_answer: Option<u32>,
+    challenge_data: Option<ChallengeData>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ChallengeData {
+    challenge: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct EnrolledAuthenticator {
+    #[serde(rename = "type")]
+    kind: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Message {
+    message: String,
+    i18n: Option<I18n>,
+    class: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct I18n {
+    key: String,
+}
+
+impl FormField {
+    fn string_value(&self) -> Option<String> {
+        self.value.as_ref()?.as_str().map(ToString::to_string)
+    }
+
+    fn errors(&self) -> Vec<&Message> {
+        let nested = self.form.iter().flat_map(|form| &form.value);
+
+        self.messages
+            .iter()
+            .flat_map(|messages| &messages.value)
+            .filter(|message| message.is_error())
+            .chain(nested.flat_map(Self::errors))
+            .collect()
+    }
+}
+
+impl Form {
+    fn field(&self, name: &str) -> Option<&FormField> {
+        self.value.iter().find(|field| field.name == name)
+    }
+}
+
+impl Remediation {
+    fn field(&self, name: &str) -> Option<&FormField> {
+        self.value.iter().find(|field| field.name == name)
+    }
+}
+
+impl Message {
+    fn is_error(&self) -> bool {
+        self.class.as_deref() == Some("ERROR")
+    }
+
+    fn to_error(&self) -> eyre::Error {
+        match self.i18n.as_ref().map(|i18n| i18n.key.as_str()) {
+            Some("incorrectPassword" | "errors.E0000004") => {
+                OktaError::AuthenticationException(self.message.clone()).into()
+            }
+            _ => eyre!("{}", self.message),
+        }
+    }
+}
+
+impl fmt::Display for CurrentAuthenticator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.display_name, &self.kind) {
+            (Some(name), _) | (None, Some(name)) => write!(f, "{name}"),
+            (None, None) => write!(f, "Authenticator"),
+        }
+    }
+}
+
+/// An authenticator (and method of verifying with it) that Okta offers
+#[derive(Debug)]
+pub struct AuthenticatorChoice {
+    label: String,
+    /// The type of authenticator, such as `app`, `password` or `security_key`
+    kind: String,
+    id: String,
+    method_type: Option<String>,
+    enrollment_id: Option<String>,
+}
+
+impl AuthenticatorChoice {
+    /// The equivalent classic factor type, used to match the configured preferences
+    #[must_use]
+    pub fn factor_kind(&self) -> Option<FactorKind> {
+        match (self.kind.as_str(), self.method_type.as_deref()) {
+            ("app", Some("push")) => Some(FactorKind::Push),
+            ("app", Some("totp" | "otp")) => Some(FactorKind::Totp),
+            ("phone_number", Some("sms")) => Some(FactorKind::Sms),
+            ("phone_number", Some("voice")) => Some(FactorKind::Call),
+            ("security_key", _) => Some(FactorKind::WebAuthn),
+            ("security_question", _) => Some(FactorKind::Question),
+            _ => None,
+        }
+    }
+
+    /// Okta FastPass needs Okta Verify on this device to answer it, so cannot be used.
+    /// Security keys need a WebAuthn authenticator to be configured.
+    #[must_use]
+    pub fn is_supported(&self, client: &Client) -> bool {
+        self.method_type.as_deref() != Some("signed_nonce")
+            && (self.kind != "security_key" || client.webauthn_authenticator().is_some())
+    }
+
+    /// Whether a configured preference selects this authenticator.
+    /// The provider is matched against the authenticator's label (such as `Google Authenticator`).
+    /// Okta does not reveal device names here, so those are not matched.
+    #[must_use]
+    pub fn matches(&self, preference: &FactorPreference) -> bool {
+        self.factor_kind() == Some(preference.kind)
+            && preference
+                .provider
+                .as_ref()
+                .is_none_or(|provider| self.label.to_lowercase().contains(&provider.to_lowercase()))
+    }
+
+    fn to_value(&self) -> Value {
+        let mut authenticator = Map::new();
+        authenticator.insert("id".to_string(), json!(self.id));
+
+        if let Some(method_type) = &self.method_type {
+            authenticator.insert("methodType".to_string(), json!(method_type));
+        }
+
+        if let Some(enrollment_id) = &self.enrollment_id {
+            authenticator.insert("enrollmentId".to_string(), json!(enrollment_id));
+        }
+
+        Value::Object(authenticator)
+    }
+}
+
+impl fmt::Display for AuthenticatorChoice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.method_type.as_deref() {
+            Some("push") => write!(f, "{} (Push)", self.label),
+            Some("totp" | "otp") => write!(f, "{} (Enter a code)", self.label),
+            Some("sms") => write!(f, "{} (SMS)", self.label),
+            Some("voice") => write!(f, "{} (Call)", self.label),
+            _ => write!(f, "{}", self.label),
+        }
+    }
+}
+
+impl IdxResponse {
+    /// The next remediation to follow, if there is one that can be followed
+    fn next_remediation(&self) -> Option<&Remediation> {
+        let remediations = &self.remediation.as_ref()?.value;
+
+        REMEDIATIONS.iter().find_map(|name| {
+            remediations
+                .iter()
+                .find(|remediation| remediation.name == *name)
+        })
+    }
+
+    /// The authenticator being challenged, from the most specific description available
+    fn current(&self) -> impl Iterator<Item = &CurrentAuthenticator> {
+        self.current_authenticator_enrollment
+            .iter()
+            .chain(self.current_authenticator.iter())
+            .map(|current| &current.value)
+    }
+
+    fn current_kind(&self) -> Option<&str> {
+        self.current().find_map(|current| current.kind.as_deref())
+    }
+
+    /// The number to tap in Okta Verify, when number matching is enabled for Push
+    fn correct_answer(&self) -> Option<u32> {
+        self.current()
+            .find_map(|current| current.contextual_data.as_ref()?.correct_answer)
+    }
+
+    /// The first error reported, either for the whole response or for a field
+    fn first_error(&self) -> Option<&Message> {
+        let fields = self
+            .remediation
+            .iter()
+            .flat_map(|remediations| &remediations.value)
+            .flat_map(|remediation| &remediation.value);
+
+        self.messages
+            .iter()
+            .flat_map(|messages| &messages.value)
+            .filter(|message| message.is_error())
+            .chain(fields.flat_map(FormField::errors))
+            .next()
+    }
+
+    /// Resolve a JSON path such as `$.authenticatorEnrollments.value[0]` to the authenticator it names
+    fn related_authenticator(&self, path: &str) -> Option<&EnrolledAuthenticator> {
+        let (collection, index) = path
+            .strip_prefix("$.")?
+            .strip_suffix(']')?
+            .split_once(".value[")?;
+        let index = index.parse::<usize>().ok()?;
+
+        match collection {
+            "authenticators" => self.authenticators.as_ref()?.value.get(index),
+            "authenticatorEnrollments" => self.authenticator_enrollments.as_ref()?.value.get(index),
+            _ => None,
+        }
+    }
+
+    /// The authenticators offered by a `select-authenticator-authenticate` remediation,
+    /// with one choice for each method of verifying with them
+    fn authenticator_choices(&self, remediation: &Remediation) -> Vec<AuthenticatorChoice> {
+        let options = remediation
+            .field("authenticator")
+            .map(|field| field.options.as_slice())
+            .unwrap_or_default();
+
+        let mut choices = Vec::new();
+
+        for option in options {
+            let OptionValue::Form { form } = &option.value else {
+                continue;
+            };
+
+            let Some(id) = form.field("id").and_then(FormField::string_value) else {
+                continue;
+            };
+
+            let kind = option
+                .relates_to
+                .as_deref()
+                .and_then(|path| self.related_authenticator(path))
+                .map(|authenticator| authenticator.kind.clone())
+                .unwrap_or_default();
+
+            let method_types = match form.field("methodType") {
+                Some(field) if !field.options.is_empty() => field
+                    .options
+                    .iter()
+                    .filter_map(|option| match &option.value {
+                        OptionValue::Value(Value::String(method_type)) => {
+                            Some(Some(method_type.clone()))
+                        }
+                        _ => None,
+                    })
+                    .collect(),
+                Some(field) => vec![field.string_value()],
+                None => vec![None],
+            };
+
+            for method_type in method_types {
+                choices.push(AuthenticatorChoice {
+                    label: option.label.clone(),
+                    kind: kind.clone(),
+                    id: id.clone(),
+                    method_type,
+                    enrollment_id: form.field("enrollmentId").and_then(FormField::string_value),
+                });
+            }
+        }
+
+        choices
+    }
+}
+
+/// Find the state token that the sign-in page passes to the sign-in widget
+///
+/// # Errors
+///
+/// This function should not error
+pub fn state_token_from_page(text: &str) -> Result<Option<String>> {
+    let re = Regex::new(r#"(?:var stateToken = '|"stateToken":")([^'"]+)"#)?;
+
+    Ok(re
+        .captures(text)
+        .map(|captures| captures[1].replace(r"\x2D", "-")))
+}
+
+impl Client {
+    /// Ask Okta which pipeline the organization authenticates with.
+    /// If it cannot be determined, the classic pipeline is assumed.
+    pub async fn detect_pipeline(&self) -> Pipeline {
+        match self
+            .get::<OrganizationInfo>(".well-known/okta-organization")
+            .await
+        {
+            Ok(info) => {
+                debug!("{} uses the {:?} pipeline", self.base_url(), info.pipeline);
+                info.pipeline
+            }
+            Err(e) => {
+                debug!("Unable to detect pipeline ({e}), assuming classic");
+                Pipeline::Classic
+            }
+        }
+    }
+
+    /// Log in with the Identity Engine, following the remediations that Okta offers
+    /// until it grants a session.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the credentials are rejected,
+    /// if there are IO problems while prompting for MFA,
+    /// if there are MFA verification errors,
+    /// or if Okta offers no remediation that can be followed.
+    pub async fn idx_login(&self, username: &str, password: &str) -> Result<()> {
+        let state_token = self.idx_state_token().await?;
+
+        let mut response = self
+            .idx_post(
+                self.base_url().join("idp/idx/introspect")?,
+                &json!({ "stateToken": state_token }),
+            )
+            .await?;
+
+        let mut password_sent = false;
+        let mut shown_answer = None;
+        let mut steps = 0;
+        let mut polls = 0;
+
+        loop {
+            if let Some(success) = &response.success {
+                debug!("Identity Engine login succeeded");
+                return self.finish_idx_login(success.href.clone()).await;
+            }
+
+            let remediation = response
+                .next_remediation()
+                .ok_or_else(|| eyre!("No supported next step found in Identity Engine response"))?;
+
+            if remediation.name == "challenge-poll" {
+                polls += 1;
+            } else {
+                steps += 1;
+            }
+            if steps > MAX_STEPS || polls > MAX_POLLS {
+                return Err(eyre!(
+                    "Identity Engine login did not finish after {steps} steps and {polls} checks of the Push"
+                ));
+            }
+
+            debug!("Following {} remediation", remediation.name);
+
+            let mut body = match remediation.name.as_str() {
+                "identify" => {
+                    let mut body = json!({ "identifier": username });
+
+                    // Some policies ask for the password separately, as a challenge
+                    if remediation.field("credentials").is_some() {
+                        body["credentials"] = json!({ "passcode": password });
+                        password_sent = true;
+                    }
+
+                    body
+                }
+                "challenge-poll" => {
+                    if let Some(answer) = response
+                        .correct_answer()
+                        .filter(|answer| shown_answer != Some(*answer))
+                    {
+                        eprintln!("Tap {answer} in Okta Verify to approve the Push");
+                        shown_answer = Some(answer);
+                    }
+
+                    sleep(
+                        remediation
+                            .refresh
+                            .map_or(DEFAULT_POLL_INTERVAL, Duration::from_millis),
+                    )
+                    .await;
+
+                    json!({})
+                }
+                "challenge-authenticator" if response.current_kind() == Some("password") => {
+                    password_sent = true;
+                    json!({ "credentials": { "passcode": password } })
+                }
+                "challenge-authenticator" => self.idx_answer(&response, remediation).await?,
+                _ => {
+                    let choices = response.authenticator_choices(remediation);
+                    let choice = self.choose_authenticator(&choices, password_sent)?;
+
+                    debug!("Authenticator: {:?}", choice);
+
+                    json!({ "authenticator": choice.to_value() })
+                }
+            };
+
+            body["stateHandle"] = json!(response.state_handle);
+
+            response = self.idx_post(remediation.href.clone(), &body).await?;
+        }
+    }
+
+    /// Get a state token for a new login from the organization's sign-in page
+    async fn idx_state_token(&self) -> Result<String> {
+        let page = self
+            .get_response(self.base_url().clone())
+            .await?
+            .text()
+            .await?;
+
+        state_token_from_page(&page)?.ok_or_else(|| {
+            eyre!(
+                "No state token found on the sign-in page of {}",
+                self.base_url()
+            )
+        })
+    }
+
+    /// Post to an Identity Engine endpoint.
+    /// Error messages in the response are returned as errors.
+    async fn idx_post<I>(&self, url: Url, body: &I) -> Result<IdxResponse>
+    where
+        I: Serialize + Sync,
+    {
+        let response = self.post_media_type(url, ION_MEDIA_TYPE, body).await?;
+        let status = response.status();
+        let text = response.text().await?;
+
+        trace!("Identity Engine response ({status}): {text}");
+
+        let response: IdxResponse = serde_json::from_str(&text)
+            .map_err(|e| eyre!("Unexpected Identity Engine response ({status}): {e}"))?;
+
+        if let Some(message) = response.first_error() {
+            return Err(message.to_error());
+        }
+
+        if !status.is_success() {
+            return Err(eyre!("Identity Engine request failed ({status})"));
+        }
+
+        Ok(response)
+    }
+
+    /// Answer the challenge of the current authenticator (other than the password)
+    async fn idx_answer(&self, response: &IdxResponse, remediation: &Remediation) -> Result<Value> {
+        if response.current_kind() == Some("security_key") {
+            return self.idx_webauthn_answer(response).await;
+        }
+
+        let field = remediation
+            .field("credentials")
+            .and_then(|field| field.form.as_ref())
+            .and_then(|form| form.value.first())
+            .ok_or_else(|| eyre!("No credentials requested by challenge"))?;
+
+        let authenticator = response
+            .current()
+            .next()
+            .ok_or_else(|| eyre!("No authenticator found in challenge"))?;
+
+        let answer = match self.totp_secret() {
+            Some(secret) if response.current_kind() == Some("app") => {
+                info!("Generating code for {authenticator} from the stored TOTP secret");
+                secret.code_at(SystemTime::now())?
+            }
+            _ => {
+                let prompt = match &field.label {
+                    Some(label) => format!("{authenticator} ({label})"),
+                    None => authenticator.to_string(),
+                };
+
+                self.allow_prompt(&format!("A code for {authenticator}"))?;
+                Password::new().with_prompt(prompt).interact()?
+            }
+        };
+
+        let mut credentials = Map::new();
+        credentials.insert(field.name.clone(), Value::String(answer));
+
+        Ok(json!({ "credentials": credentials }))
+    }
+
+    /// Answer a security key challenge, by having the configured authenticator sign it
+    async fn idx_webauthn_answer(&self, response: &IdxResponse) -> Result<Value> {
+        let challenge = response
+            .current()
+            .find_map(|current| current.contextual_data.as_ref()?.challenge_data.as_ref())
+            .ok_or_else(|| eyre!("No challenge found for security key"))?;
+
+        let credential_ids = response
+            .current()
+            .filter_map(|current| current.credential_id.as_deref())
+            .map(|id| b64url.decode(id.trim_end_matches('=')))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let (client_data, assertion) = self
+            .sign_webauthn_challenge(&challenge.challenge, credential_ids)
+            .await?;
+
+        Ok(json!({
+            "credentials": {
+                "clientData": b64url.encode(&client_data),
+                "authenticatorData": b64url.encode(&assertion.authenticator_data),
+                "signatureData": b64url.encode(&assertion.signature),
+            }
+        }))
+    }
+
+    /// Pick the authenticator to verify with.
+    /// The password is always used first, then the configured preferences
+    /// (or the TOTP secret) decide, and otherwise the user is prompted.
+    fn choose_authenticator<'a>(
+        &self,
+        choices: &'a [AuthenticatorChoice],
+        password_sent: bool,
+    ) -> Result<&'a AuthenticatorChoice> {
+        if let Some(password) = choices
+            .iter()
+            .find(|choice| !password_sent && choice.kind == "password")
+        {
+            return Ok(password);
+        }
+
+        let choices = choices
+            .iter()
+            .filter(|choice| choice.is_supported(self) && choice.kind != "password")
+            .collect::<Vec<_>>();
+
+        let preferred = self
+            .factor_preferences()
+            .iter()
+            .find_map(|preference| choices.iter().find(|choice| choice.matches(preference)));
+
+        if preferred.is_none() && !self.factor_preferences().is_empty() {
+            warn!("None of the preferred MFA options are available");
+        }
+
+        let preferred = preferred.copied().or_else(|| {
+            self.totp_secret().and_then(|_| {
+                choices
+                    .iter()
+                    .find(|choice| choice.factor_kind() == Some(FactorKind::Totp))
+                    .copied()
+            })
+        });
+
+        match (choices.len(), preferred) {
+            (0, _) => Err(eyre!(
+                "MFA is required, but the user has no supported factors"
+            )),
+            (_, Some(choice)) => {
+                info!("Using preferred MFA option ({choice})");
+                Ok(choice)
+            }
+            (1, None) => {
+                info!(
+                    "Only one MFA option is supported ({}), using it",
+                    choices[0]
+                );
+                Ok(choices[0])
+            }
+            _ => {
+                self.allow_prompt("A choice of MFA factor (--factor)")?;
+                let selection = dialoguer::Select::new()
+                    .with_prompt("Choose MFA Option")
+                    .items(&choices)
+                    .default(0)
+                    .interact()?;
+
+                Ok(choices[selection])
+            }
+        }
+    }
+
+    /// Follow the success redirect, which sets the session cookie
+    async fn finish_idx_login(&self, url: Url) -> Result<()> {
+        self.get_response(url).await?;
+
+        self.stored_session()
+            .map(|_| ())
+            .ok_or_else(|| eyre!("Identity Engine login succeeded, but no session was created"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use wiremock::matchers::{body_json, body_partial_json, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn remediation(server: &MockServer, name: &str, href: &str, value: &Value) -> Value {
+        json!({
+            "rel": ["create-form"],
+            "name": name,
+            "href": format!("{}{href}", server.uri()),
+            "method": "POST",
+            "refresh": 10,
+            "value": value
+        })
+    }
+
+    fn state_handle() -> Value {
+        json!({ "name": "stateHandle", "required": true, "value": "STATE_HANDLE", "visible": false })
+    }
+
+    async fn mount_sign_in_page(server: &MockServer) {
+        Mock::given(method("GET"))
+            .and(path("/"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_string(r"<script>var stateToken = 'STATE\x2DTOKEN';</script>"),
+            )
+            .mount(server)
+            .await;
+
+        Mock::given(method("POST"))
+            .and(path("/idp/idx/introspect"))
+            .and(body_json(json!({ "stateToken": "STATE-TOKEN" })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "stateHandle": "STATE_HANDLE",
+                "remediation": {
+                    "type": "array",
+                    "value": [remediation(server, "identify", "/idp/idx/identify", &json!([
+                        { "name": "identifier", "label": "Username" },
+                        {
+                            "name": "credentials",
+                            "type": "object",
+                            "form": { "value": [{ "name": "passcode", "label": "Password", "secret": true }] }
+                        },
+                        state_handle()
+                    ]))]
+                }
+            })))
+            .expect(1)
+            .mount(server)
+            .await;
+    }
+
+    #[test]
+    fn finds_state_token() -> Result<()> {
+        assert_eq!(
+            state_token_from_page(r"var stateToken = '00ab\x2Dcd';")?,
+            Some("00ab-cd".to_string())
+        );
+        assert_eq!(
+            state_token_from_page(r#"{"stateToken":"00abcd","other":1}"#)?,
+            Some("00abcd".to_string())
+        );
+        assert_eq!(state_token_from_page("<html></html>")?, None);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn detects_pipeline() -> Result<()> {
+        let server = MockServer::start().await;
+        let client = Client::unauthenticated(Url::parse(&server.uri())?, "user")?;
+
+        // Without the endpoint, classic is assumed
+        assert_eq!(client.detect_pipeline().await, Pipeline::Classic);
+
+        Mock::given(method("GET"))
+            .and(path("/.well-known/okta-organization"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "id": "00o1",
+                "pipeline": "idx"
+            })))
+            .mount(&server)
+            .await;
+
+        assert_eq!(client.detect_pipeline().await, Pipeline::Idx);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn logs_in_with_push() -> Result<()> {
+        let server = MockServer::start().await;
+        mount_sign_in_page(&server).await;
+
+        Mock::given(method("POST"))
+            .and(path("/idp/idx/identify"))
+            .and(body_json(json!({
+                "identifier": "user",
+                "credentials": { "passcode": "password" },
+                "stateHandle": "STATE_HANDLE"
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "stateHandle": "STATE_HANDLE",
+                "authenticatorEnrollments": {
+                    "type": "array",
+                    "value": [{ "type": "app", "key": "okta_verify", "displayName": "Okta Verify" }]
+                },
+                "remediation": {
+                    "type": "array",
+                    "value": [remediation(&server, "select-authenticator-authenticate", "/idp/idx/challenge", &json!([
+                        {
+                            "name": "authenticator",
+                            "type": "object",
+                            "options": [{
+                                "label": "Okta Verify",
+                                "value": {
+                                    "form": {
+                                        "value": [
+                                            { "name": "id", "required": true, "value": "AUT_ID", "mutable": false },
+                                            {
+                                                "name": "methodType",
+                                                "type": "string",
+                                                "options": [
+                                                    { "label": "Enter a code", "value": "totp" },
+                                                    { "label": "Get a push notification", "value": "push" }
+                                                ]
+                                            }
+                                        ]
+                                    }
+                                },
+                                "relatesTo": "$.authenticatorEnrollments.value[0]"
+                            }]
+                        },
+                        state_handle()
+                    ]))]
+                }
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let waiting = json!({
+            "stateHandle": "STATE_HANDLE",
+            "currentAuthenticator": {
+                "type": "object",
+                "value": {
+                    "type": "app",
+                    "displayName": "Okta Verify",
+                    "contextualData": { "correctAnswer": 42 }
+                }
+            },
+            "remediation": {
+                "type": "array",
+                "value": [remediation(&server, "challenge-poll", "/idp/idx/authenticators/poll", &json!([state_handle()]))]
+            }
+        });
+
+        Mock::given(method("POST"))
+            .and(path("/idp/idx/challenge"))
+            .and(body_json(json!({
+                "authenticator": { "id": "AUT_ID", "methodType": "push" },
+                "stateHandle": "STATE_HANDLE"
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(&waiting))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        Mock::given(method("POST"))
+            .and(path("/idp/idx/authenticators/poll"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(&waiting))
+            .up_to_n_times(1)
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        Mock::given(method("POST"))
+            .and(path("/idp/idx/authenticators/poll"))
+            .and(body_partial_json(json!({ "stateHandle": "STATE_HANDLE" })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "stateHandle": "STATE_HANDLE",
+                "success": {
+                    "name": "success-redirect",
+                    "href": format!("{}/login/token/redirect?stateToken=STATE_TOKEN", server.uri())
+                }
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        Mock::given(method("GET"))
+            .and(path("/login/token/redirect"))
+            .respond_with(
+                ResponseTemplate::new(200).insert_header("Set-Cookie", "sid=SESSION_ID; Path=/"),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let mut client = Client::unauthenticated(Url::parse(&server.uri())?, "user")?;
+        client.set_factor_preferences(vec!["push".parse()?]);
+
+        client.idx_login("user", "password").await?;
+
+        assert_eq!(
+            client.stored_session().map(|session| session.session_id),
+            Some("SESSION_ID".to_string())
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn rejects_incorrect_password() -> Result<()> {
+        let server = MockServer::start().await;
+        mount_sign_in_page(&server).await;
+
+        Mock::given(method("POST"))
+            .and(path("/idp/idx/identify"))
+            .respond_with(ResponseTemplate::new(401).set_body_json(json!({
+                "stateHandle": "STATE_HANDLE",
+                "messages": {
+                    "type": "array",
+                    "value": [{
+                        "message": "Password is incorrect",
+                        "i18n": { "key": "incorrectPassword" },
+                        "class": "ERROR"
+                    }]
+                }
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = Client::unauthenticated(Url::parse(&server.uri())?, "user")?;
+        let error = client.idx_login("user", "wrong").await.unwrap_err();
+
+        assert!(matches!(
+            error.downcast_ref(),
+            Some(OktaError::AuthenticationException(_))
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn fails_on_error_status_without_messages() -> Result<()> {
+        let server = MockServer::start().await;
+        mount_sign_in_page(&server).await;
+
+        Mock::given(method("POST"))
+            .and(path("/idp/idx/identify"))
+            .respond_with(ResponseTemplate::new(500).set_body_json(json!({
+                "stateHandle": "STATE_HANDLE"
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = Client::unauthenticated(Url::parse(&server.uri())?, "user")?;
+        let error = client.idx_login("user", "password").await.unwrap_err();
+
+        assert!(error.to_string().contains("500"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn gives_up_on_repeated_remediations() -> Result<()> {
+        let server = MockServer::start().await;
+        mount_sign_in_page(&server).await;
+
+        // Keep asking for the username, however many times it is given
+        Mock::given(method("POST"))
+            .and(path("/idp/idx/identify"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "stateHandle": "STATE_HANDLE",
+                "remediation": {
+                    "type": "array",
+                    "value": [remediation(&server, "identify", "/idp/idx/identify", &json!([
+                        { "name": "identifier", "label": "Username" },
+                        state_handle()
+                    ]))]
+                }
+            })))
+            .expect(u64::try_from(MAX_STEPS)?)
+            .mount(&server)
+            .await;
+
+        let client = Client::unauthenticated(Url::parse(&server.uri())?, "user")?;
+        let error = client.idx_login("user", "password").await.unwrap_err();
+
+        assert!(error.to_string().contains("did not finish"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn matches_preferences_by_label() -> Result<()> {
+        let choice = AuthenticatorChoice {
+            label: "Google Authenticator".to_string(),
+            kind: "app".to_string(),
+            id: "AUT_ID".to_string(),
+            method_type: Some("otp".to_string()),
+            enrollment_id: None,
+        };
+
+        assert!(choice.matches(&"totp".parse()?));
+        assert!(choice.matches(&"totp:google".parse()?));
+        assert!(!choice.matches(&"totp:okta".parse()?));
+        assert!(!choice.matches(&"push".parse()?));
+
+        Ok(())
+    }
+}
@@ -2,13 +2,26 @@ pub mod applications;
 pub mod auth;
 pub mod client;
 pub mod factors;
+pub mod idx;
 pub mod saml;
 pub mod sessions;
 pub mod sso;
+pub mod totp;
+pub mod webauthn;
 
+use eyre::Result;
 use serde::Deserialize;
 use url::Url;
 
+/// The URL of an Okta organization that has not configured a custom domain
+///
+/// # Errors
+///
+/// Will return `Err` if the organization name does not form a valid URL
+pub fn default_base_url(organization: &str) -> Result<Url> {
+    Url::parse(&format!("https://{organization}.okta.com/")).map_err(Into::into)
+}
+
 #[derive(Deserialize, Debug)]
 #[serde(untagged)]
 pub enum Links {
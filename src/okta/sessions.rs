@@ -3,9 +3,13 @@ use crate::okta::client::Client;
 use std::collections::HashSet;
 use std::fmt;
 
+use cookie::Cookie;
 use eyre::Result;
 use itertools::Itertools;
+use reqwest::cookie::CookieStore;
 use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+use url::Url;
 
 #[derive(Serialize, Debug)]
 #[serde(rename_all = "camelCase")]
@@ -27,6 +31,14 @@ pub struct Session {
     pub mfa_active: bool,
 }
 
+/// The cookies needed to resume an Okta session in a later invocation
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct StoredSession {
+    pub session_id: String,
+    pub device_token: Option<String>,
+}
+
 #[allow(dead_code)]
 #[derive(PartialEq, Eq, Hash)]
 pub enum SessionProperties {
@@ -115,4 +127,214 @@ impl Client {
 
         Ok(())
     }
+
+    /// Get the session identified by the client's `sid` cookie
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if there is no such session, or it has expired
+    pub async fn current_session(&self) -> Result<Session> {
+        self.get("api/v1/sessions/me").await
+    }
+
+    /// Whether the client's `sid` cookie identifies an active session
+    pub async fn is_session_active(&self) -> bool {
+        match self.current_session().await {
+            Ok(session) => matches!(session.status, SessionStatus::Active),
+            Err(e) => {
+                debug!("Okta session is no longer valid: {e}");
+                false
+            }
+        }
+    }
+
+    /// End the session identified by the client's `sid` cookie
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if there is no such session, or it could not be ended
+    pub async fn end_session(&self) -> Result<()> {
+        self.delete("api/v1/sessions/me").await
+    }
+
+    /// Get the session and device token cookies currently held by the client
+    #[must_use]
+    pub fn stored_session(&self) -> Option<StoredSession> {
+        let cookies = self.cookies.cookies(self.base_url())?;
+        let cookies = cookies.to_str().ok()?;
+
+        let cookie = |name: &str| {
+            Cookie::split_parse(cookies)
+                .filter_map(Result::ok)
+                .find(|cookie| cookie.name() == name)
+                .map(|cookie| cookie.value().to_string())
+        };
+
+        Some(StoredSession {
+            session_id: cookie("sid")?,
+            device_token: cookie("DT"),
+        })
+    }
+
+    /// Resume a stored session, by restoring its cookies onto the client
+    pub fn restore_session(&mut self, session: &StoredSession) {
+        if let Some(device_token) = &session.device_token {
+            self.cookies
+                .add_cookie_str(&format!("DT={device_token}"), self.base_url());
+        }
+
+        self.set_session_id(&session.session_id);
+    }
+
+    /// The keyring entry that an organization's session is stored in
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the keyring entry cannot be created
+    pub fn session_keyring(organization: &str, username: &str) -> Result<keyring::Entry> {
+        keyring::Entry::new(&format!("oktaws::okta::{organization}::session"), username)
+            .map_err(Into::into)
+    }
+
+    #[must_use]
+    pub fn get_cached_session(keyring: &keyring::Entry) -> Option<StoredSession> {
+        serde_json::from_str(&keyring.get_password().ok()?).ok()
+    }
+
+    pub fn set_cached_session(&self, keyring: &keyring::Entry) {
+        let Some(session) = self.stored_session() else {
+            return;
+        };
+
+        debug!("Saving Okta session for {}", self.base_url());
+
+        // Don't treat this as a failure, as it is not a hard requirement
+        let result = serde_json::to_string(&session)
+            .map_err(eyre::Error::from)
+            .and_then(|session| keyring.set_password(&session).map_err(Into::into));
+        if let Err(e) = result {
+            warn!("Error while saving session: {}", e);
+        }
+    }
+
+    /// End the stored session for an organization (if any), and forget it
+    ///
+    /// Returns whether there was a stored session
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the stored session cannot be removed from the keyring
+    pub async fn logout(organization: &str, username: &str, base_url: &Url) -> Result<bool> {
+        let keyring = Self::session_keyring(organization, username)?;
+        let Some(session) = Self::get_cached_session(&keyring) else {
+            return Ok(false);
+        };
+
+        let mut client = Self::unauthenticated(base_url.clone(), username)?;
+        client.restore_session(&session);
+
+        // The session may already have expired, which is fine
+        if let Err(e) = client.end_session().await {
+            debug!("Could not end Okta session: {e}");
+        }
+
+        keyring.delete_credential()?;
+
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::json;
+    use wiremock::matchers::{header_regex, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn stored_session() -> StoredSession {
+        StoredSession {
+            session_id: "SESSION_ID".to_string(),
+            device_token: Some("DEVICE_TOKEN".to_string()),
+        }
+    }
+
+    fn client_for(server: &MockServer) -> Result<Client> {
+        let mut client = Client::unauthenticated(Url::parse(&server.uri())?, "user")?;
+        client.restore_session(&stored_session());
+        Ok(client)
+    }
+
+    #[test]
+    fn restored_session_round_trips() -> Result<()> {
+        let mut client = Client::unauthenticated(Url::parse("https://example.okta.com/")?, "user")?;
+        assert_eq!(client.stored_session(), None);
+
+        client.restore_session(&stored_session());
+        assert_eq!(client.stored_session(), Some(stored_session()));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn active_session_is_reusable() -> Result<()> {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/sessions/me"))
+            .and(header_regex("cookie", "sid=SESSION_ID"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "id": "SESSION_ID",
+                "login": "user@example.com",
+                "userId": "USER_ID",
+                "expiresAt": "2026-01-01T00:00:00.000Z",
+                "status": "ACTIVE",
+                "lastPasswordVerification": "2025-12-31T22:00:00.000Z",
+                "lastFactorVerification": "2025-12-31T22:00:00.000Z",
+                "amr": ["pwd", "mfa"],
+                "mfaActive": true
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        assert!(client_for(&server)?.is_session_active().await);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn expired_session_is_not_reusable() -> Result<()> {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/sessions/me"))
+            .respond_with(ResponseTemplate::new(404).set_body_json(json!({
+                "errorCode": "E0000007",
+                "errorSummary": "Not found: Resource not found: me (Session)",
+                "errorLink": "E0000007",
+                "errorId": "ERROR_ID",
+                "errorCauses": []
+            })))
+            .mount(&server)
+            .await;
+
+        assert!(!client_for(&server)?.is_session_active().await);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn ends_session() -> Result<()> {
+        let server = MockServer::start().await;
+        Mock::given(method("DELETE"))
+            .and(path("/api/v1/sessions/me"))
+            .and(header_regex("cookie", "sid=SESSION_ID"))
+            .respond_with(ResponseTemplate::new(204))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        client_for(&server)?.end_session().await?;
+
+        Ok(())
+    }
 }
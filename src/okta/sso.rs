@@ -12,11 +12,33 @@ use serde::Deserialize;
 use tracing::trace;
 use url::Url;
 
-use crate::aws::sso::{AppInstance, Client as SsoClient};
+use crate::aws::sso::{AppInstance, Client as SsoClient, DEFAULT_SSO_REGION};
 
 pub struct SsoOrgAuth {
     pub org_id: String,
     pub auth_code: String,
+    /// Region of the IAM Identity Center instance
+    pub region: String,
+}
+
+/// Find the region in an AWS sign-in or portal URL,
+/// such as `https://eu-west-1.signin.aws.amazon.com/platform/saml/acs/...`
+/// or `https://portal.sso.eu-west-1.amazonaws.com/...`
+#[must_use]
+pub fn region_from_url(url: &Url) -> Option<String> {
+    let host = url.host_str()?;
+
+    let region = if let Some(rest) = host.strip_prefix("portal.sso.") {
+        rest.split('.').next()
+    } else {
+        host.split_once(".signin.").map(|(region, _)| region)
+    }?;
+
+    if region.is_empty() {
+        None
+    } else {
+        Some(region.to_string())
+    }
 }
 
 #[derive(Clone, Deserialize, Debug)]
@@ -56,11 +78,11 @@ impl Client {
     /// # Errors
     ///
     /// Will return `Err` if the cookie is not found or cannot be parsed
-    fn extract_org_auth_from_cookie(cookies: &Arc<Jar>) -> Result<(String, String)> {
+    fn extract_org_auth_from_cookie(cookies: &Arc<Jar>, region: &str) -> Result<(String, String)> {
         let cookie_str = cookies
-            .cookies(&Url::parse(
-                "https://us-east-1.signin.aws.amazon.com/platform",
-            )?)
+            .cookies(&Url::parse(&format!(
+                "https://{region}.signin.aws.amazon.com/platform"
+            ))?)
             .ok_or_else(|| eyre!("No cookies found"))?;
 
         let workflow_state_cookie = Cookie::split_parse_encoded(cookie_str.to_str()?)
@@ -153,9 +175,18 @@ impl Client {
             .send()
             .await?;
 
+        // The SAML response is posted to the sign-in endpoint in the instance's region
+        let region = self
+            .sso_region()
+            .map(ToString::to_string)
+            .or_else(|| region_from_url(&response.url))
+            .or_else(|| region_from_url(aws_response.url()))
+            .unwrap_or_else(|| DEFAULT_SSO_REGION.to_string());
+        trace!("Using IAM Identity Center region {}", region);
+
         // Try cookie-based extraction first (newer AWS flow)
         let (org_id, auth_code) =
-            Self::extract_org_auth_from_cookie(&cookies).or_else(|cookie_err| {
+            Self::extract_org_auth_from_cookie(&cookies, &region).or_else(|cookie_err| {
                 trace!(
                     "Cookie extraction failed: {}, falling back to URL extraction",
                     cookie_err,
@@ -163,7 +194,11 @@ impl Client {
                 Self::extract_org_auth_from_url(&aws_response)
             })?;
 
-        Ok(SsoOrgAuth { org_id, auth_code })
+        Ok(SsoOrgAuth {
+            org_id,
+            auth_code,
+            region,
+        })
     }
 
     /// Given an `amazon_aws_sso` identity center `AppLink`, use the paginated assignment APIs
@@ -181,7 +216,8 @@ impl Client {
     ) -> Result<Vec<AppLinkAccountRoleMapping>> {
         let app_name = app_link.clone().label;
         let org_auth = self.get_org_auth_for_app_link(app_link).await?;
-        let sso_client = SsoClient::new(&org_auth.org_id, &org_auth.auth_code).await?;
+        let sso_client =
+            SsoClient::new(&org_auth.org_id, &org_auth.auth_code, &org_auth.region).await?;
 
         Ok(sso_client
             .list_accounts_and_roles()
@@ -208,8 +244,40 @@ impl Client {
     /// Will return `Err` if there are any errors while fetching the roles.
     pub async fn all_app_instances(&self, app_link: AppLink) -> Result<Vec<AppInstance>> {
         let org_auth = self.get_org_auth_for_app_link(app_link).await?;
-        let sso_client = SsoClient::new(&org_auth.org_id, &org_auth.auth_code).await?;
+        let sso_client =
+            SsoClient::new(&org_auth.org_id, &org_auth.auth_code, &org_auth.region).await?;
 
         sso_client.app_instances().await
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_region_in_signin_urls() -> Result<()> {
+        assert_eq!(
+            region_from_url(&Url::parse(
+                "https://eu-west-1.signin.aws.amazon.com/platform/saml/acs/abc"
+            )?),
+            Some("eu-west-1".to_string())
+        );
+        assert_eq!(
+            region_from_url(&Url::parse(
+                "https://portal.sso.ap-southeast-2.amazonaws.com/auth/sso-token"
+            )?),
+            Some("ap-southeast-2".to_string())
+        );
+        assert_eq!(
+            region_from_url(&Url::parse("https://signin.aws.amazon.com/saml")?),
+            None
+        );
+        assert_eq!(
+            region_from_url(&Url::parse("https://d-1234567890.awsapps.com/start")?),
+            None
+        );
+
+        Ok(())
+    }
+}
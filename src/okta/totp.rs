@@ -0,0 +1,190 @@
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use data_encoding::BASE32_NOPAD;
+use eyre::{Result, eyre};
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+use tracing::debug;
+
+/// Okta's TOTP factors use 30-second time steps
+pub const TIME_STEP: u64 = 30;
+
+/// Okta's TOTP factors use 6-digit codes
+pub const DIGITS: u32 = 6;
+
+/// Environment variable that `oktaws totp-secret` reads the secret from, instead of prompting
+pub const SECRET_VAR: &str = "OKTAWS_TOTP_SECRET";
+
+/// A shared secret for generating TOTP codes (as shown when enrolling an authenticator app)
+#[derive(Clone, PartialEq, Eq)]
+pub struct TotpSecret {
+    encoded: String,
+    key: Vec<u8>,
+}
+
+// Avoid leaking the secret into logs
+impl fmt::Debug for TotpSecret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("TotpSecret(..)")
+    }
+}
+
+impl TotpSecret {
+    /// Parse a base32-encoded secret, ignoring case, spaces, dashes and padding
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the secret is empty or not valid base32
+    pub fn parse(secret: &str) -> Result<Self> {
+        let encoded = secret
+            .chars()
+            .filter(|c| !c.is_whitespace() && !matches!(c, '-' | '='))
+            .map(|c| c.to_ascii_uppercase())
+            .collect::<String>();
+
+        let key = BASE32_NOPAD
+            .decode(encoded.as_bytes())
+            .map_err(|e| eyre!("Invalid TOTP secret: {e}"))?;
+
+        if key.is_empty() {
+            return Err(eyre!("Invalid TOTP secret: it is empty"));
+        }
+
+        Ok(Self { encoded, key })
+    }
+
+    /// The keyring entry that an organization's TOTP secret is stored in
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the keyring entry cannot be created
+    pub fn keyring(organization: &str, username: &str) -> Result<keyring::Entry> {
+        keyring::Entry::new(&format!("oktaws::okta::{organization}::totp"), username)
+            .map_err(Into::into)
+    }
+
+    #[must_use]
+    pub fn get_cached(keyring: &keyring::Entry) -> Option<Self> {
+        let secret = keyring.get_password().ok()?;
+
+        Self::parse(&secret)
+            .inspect_err(|e| debug!("Ignoring stored TOTP secret: {e}"))
+            .ok()
+    }
+
+    /// # Errors
+    ///
+    /// Will return `Err` if the secret cannot be saved to the keyring
+    pub fn set_cached(&self, keyring: &keyring::Entry) -> Result<()> {
+        keyring.set_password(&self.encoded).map_err(Into::into)
+    }
+
+    /// Generate the code for the time step containing `time`
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if `time` is before the UNIX epoch
+    pub fn code_at(&self, time: SystemTime) -> Result<String> {
+        let seconds = time.duration_since(UNIX_EPOCH)?.as_secs();
+        hotp(&self.key, seconds / TIME_STEP, DIGITS)
+    }
+}
+
+/// Generate an HMAC-SHA1 one-time password (RFC 4226) for a counter
+///
+/// # Errors
+///
+/// Will return `Err` if the key is rejected by HMAC
+pub fn hotp(key: &[u8], counter: u64, digits: u32) -> Result<String> {
+    let mut mac = Hmac::<Sha1>::new_from_slice(key).map_err(|e| eyre!("{e}"))?;
+    mac.update(&counter.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+
+    // Dynamic truncation
+    let offset = usize::from(hash[hash.len() - 1] & 0x0f);
+    let binary = u32::from_be_bytes([
+        hash[offset] & 0x7f,
+        hash[offset + 1],
+        hash[offset + 2],
+        hash[offset + 3],
+    ]);
+
+    Ok(format!(
+        "{:0width$}",
+        binary % 10_u32.pow(digits),
+        width = digits as usize
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Duration;
+
+    // The SHA1 key from RFC 4226 and RFC 6238
+    const KEY: &[u8] = b"12345678901234567890";
+
+    #[test]
+    fn rfc4226_test_vectors() -> Result<()> {
+        let expected = [
+            "755224", "287082", "359152", "969429", "338314", "254676", "287922", "162583",
+            "399871", "520489",
+        ];
+
+        for (counter, code) in (0..).zip(expected) {
+            assert_eq!(hotp(KEY, counter, 6)?, code);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn rfc6238_test_vectors() -> Result<()> {
+        let expected = [
+            (59, "94287082"),
+            (1_111_111_109, "07081804"),
+            (1_111_111_111, "14050471"),
+            (1_234_567_890, "89005924"),
+            (2_000_000_000, "69279037"),
+            (20_000_000_000, "65353130"),
+        ];
+
+        for (time, code) in expected {
+            assert_eq!(hotp(KEY, time / TIME_STEP, 8)?, code);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn generates_six_digit_codes_from_base32_secret() -> Result<()> {
+        // "12345678901234567890" in base32, as an authenticator app would show it
+        let secret = TotpSecret::parse("gezd gnbv gy3t qojq gezd gnbv gy3t qojq")?;
+
+        assert_eq!(
+            secret.code_at(UNIX_EPOCH + Duration::from_secs(59))?,
+            "287082"
+        );
+        assert_eq!(
+            secret.code_at(UNIX_EPOCH + Duration::from_secs(1_111_111_109))?,
+            "081804"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_invalid_secrets() {
+        assert!(TotpSecret::parse("not base32!").is_err());
+        assert!(TotpSecret::parse("").is_err());
+    }
+
+    #[test]
+    fn does_not_debug_print_secret() -> Result<()> {
+        let secret = TotpSecret::parse("GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ")?;
+        assert_eq!(format!("{secret:?}"), "TotpSecret(..)");
+        Ok(())
+    }
+}
@@ -0,0 +1,395 @@
+use crate::okta::auth::LoginResponse;
+use crate::okta::client::Client;
+use crate::okta::factors::FactorVerificationRequest;
+
+use std::env;
+use std::fmt;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use base64::engine::Engine;
+use base64::engine::general_purpose::{STANDARD as b64, URL_SAFE_NO_PAD as b64url};
+use eyre::{Result, eyre};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::debug;
+use url::Url;
+
+/// Environment variable naming a command to use as the WebAuthn authenticator
+pub const COMMAND_VAR: &str = "OKTAWS_WEBAUTHN_COMMAND";
+
+/// The parameters of a WebAuthn `navigator.credentials.get()` call
+#[derive(Debug, PartialEq, Eq)]
+pub struct AssertionRequest {
+    pub rp_id: String,
+    pub client_data_hash: Vec<u8>,
+    pub credential_ids: Vec<Vec<u8>>,
+}
+
+/// The parts of an authenticator's assertion that Okta verifies
+#[derive(Debug, PartialEq, Eq)]
+pub struct Assertion {
+    pub authenticator_data: Vec<u8>,
+    pub signature: Vec<u8>,
+}
+
+/// Something that can sign WebAuthn challenges, such as a security key
+pub trait Authenticator: fmt::Debug + Send + Sync {
+    /// Produce an assertion over the client data hash with one of the allowed credentials
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if no assertion could be produced
+    fn get_assertion(&self, request: &AssertionRequest) -> Result<Assertion>;
+}
+
+/// An authenticator implemented by an external command.
+///
+/// The command receives the request as JSON on stdin (`rpId`, `clientDataHash`, `credentialIds`),
+/// and must print the assertion as JSON on stdout (`authenticatorData`, `signature`).
+/// All binary values are unpadded base64url.
+#[derive(Debug)]
+pub struct CommandAuthenticator {
+    command: String,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct CommandRequest<'a> {
+    rp_id: &'a str,
+    client_data_hash: String,
+    credential_ids: Vec<String>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct CommandResponse {
+    authenticator_data: String,
+    signature: String,
+}
+
+impl CommandAuthenticator {
+    #[must_use]
+    pub const fn new(command: String) -> Self {
+        Self { command }
+    }
+
+    /// Use the command named by `OKTAWS_WEBAUTHN_COMMAND`, if set
+    #[must_use]
+    pub fn from_env() -> Option<Self> {
+        env::var(COMMAND_VAR)
+            .ok()
+            .filter(|command| !command.is_empty())
+            .map(Self::new)
+    }
+
+    fn shell(&self) -> Command {
+        if cfg!(windows) {
+            let mut command = Command::new("cmd");
+            command.args(["/C", &self.command]);
+            command
+        } else {
+            let mut command = Command::new("sh");
+            command.args(["-c", &self.command]);
+            command
+        }
+    }
+}
+
+impl Authenticator for CommandAuthenticator {
+    fn get_assertion(&self, request: &AssertionRequest) -> Result<Assertion> {
+        let input = serde_json::to_vec(&CommandRequest {
+            rp_id: &request.rp_id,
+            client_data_hash: b64url.encode(&request.client_data_hash),
+            credential_ids: request
+                .credential_ids
+                .iter()
+                .map(|id| b64url.encode(id))
+                .collect(),
+        })?;
+
+        debug!("Requesting WebAuthn assertion from `{}`", self.command);
+
+        // stderr is inherited, so that the command can ask the user to touch their key
+        let mut child = self
+            .shell()
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+
+        child
+            .stdin
+            .take()
+            .ok_or_else(|| eyre!("Could not open stdin of WebAuthn command"))?
+            .write_all(&input)?;
+
+        let output = child.wait_with_output()?;
+        if !output.status.success() {
+            return Err(eyre!("WebAuthn command failed ({})", output.status));
+        }
+
+        let response: CommandResponse = serde_json::from_slice(&output.stdout)?;
+
+        Ok(Assertion {
+            authenticator_data: b64url.decode(response.authenticator_data)?,
+            signature: b64url.decode(response.signature)?,
+        })
+    }
+}
+
+/// The `CollectedClientData` that the authenticator signs (via its hash)
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ClientData {
+    #[serde(rename = "type")]
+    type_: &'static str,
+    challenge: String,
+    origin: String,
+    cross_origin: bool,
+}
+
+impl Client {
+    /// Have the configured authenticator sign a challenge issued by this organization,
+    /// returning the client data that was signed along with the assertion
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if no authenticator is configured,
+    /// or if the authenticator cannot sign the challenge.
+    pub async fn sign_webauthn_challenge(
+        &self,
+        challenge: &str,
+        credential_ids: Vec<Vec<u8>>,
+    ) -> Result<(Vec<u8>, Assertion)> {
+        let authenticator = self.webauthn_authenticator().ok_or_else(|| {
+            eyre!("No WebAuthn authenticator configured (set {COMMAND_VAR} to enable one)")
+        })?;
+
+        let client_data = serde_json::to_vec(&ClientData {
+            type_: "webauthn.get",
+            challenge: challenge.to_string(),
+            origin: self.base_url().origin().ascii_serialization(),
+            cross_origin: false,
+        })?;
+
+        let request = AssertionRequest {
+            rp_id: self
+                .base_url()
+                .host_str()
+                .ok_or_else(|| eyre!("No host found in {}", self.base_url()))?
+                .to_string(),
+            client_data_hash: Sha256::digest(&client_data).to_vec(),
+            credential_ids,
+        };
+
+        // Waiting for the user to touch their key can take a while, so keep it off the async workers
+        let assertion =
+            tokio::task::spawn_blocking(move || authenticator.get_assertion(&request)).await??;
+
+        Ok((client_data, assertion))
+    }
+
+    /// Verify a WebAuthn factor, by having the configured authenticator sign Okta's challenge
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if no authenticator is configured,
+    /// if Okta does not issue a challenge,
+    /// or if the authenticator cannot sign it.
+    pub async fn verify_webauthn(&self, url: Url, state_token: String) -> Result<LoginResponse> {
+        if self.webauthn_authenticator().is_none() {
+            return Err(eyre!(
+                "No WebAuthn authenticator configured (set {COMMAND_VAR} to enable one)"
+            ));
+        }
+
+        // Ask Okta for a challenge
+        let request = FactorVerificationRequest::WebAuthn { state_token };
+        let response: LoginResponse = self.post_absolute(url.clone(), &request).await?;
+
+        let state_token = response
+            .state_token
+            .clone()
+            .ok_or_else(|| eyre!("No state token found in factor challenge response"))?;
+
+        let factor = response
+            .challenged_factor()
+            .ok_or_else(|| eyre!("No factor found in factor challenge response"))?;
+
+        let challenge = factor
+            .challenge()
+            .ok_or_else(|| eyre!("No challenge found in factor challenge response"))?;
+
+        let credential_ids = factor
+            .credential_id()
+            .map(|id| b64url.decode(id.trim_end_matches('=')))
+            .transpose()?
+            .into_iter()
+            .collect();
+
+        let (client_data, assertion) = self
+            .sign_webauthn_challenge(challenge, credential_ids)
+            .await?;
+
+        let request = FactorVerificationRequest::WebAuthnAssertion {
+            state_token,
+            client_data: b64.encode(&client_data),
+            authenticator_data: b64.encode(&assertion.authenticator_data),
+            signature_data: b64.encode(&assertion.signature),
+        };
+
+        self.post_absolute(url, &request).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use p256::ecdsa::signature::{Signer, Verifier};
+    use p256::ecdsa::{Signature, SigningKey, VerifyingKey};
+    use serde_json::{Value, json};
+    use wiremock::matchers::{body_partial_json, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    const CREDENTIAL_ID: &[u8] = b"credential";
+
+    /// A software authenticator, holding a single P-256 credential
+    #[derive(Debug)]
+    struct SoftwareAuthenticator {
+        key: SigningKey,
+    }
+
+    impl SoftwareAuthenticator {
+        fn new() -> Self {
+            Self {
+                key: SigningKey::from_bytes(&[7; 32].into()).unwrap(),
+            }
+        }
+
+        fn verifying_key(&self) -> VerifyingKey {
+            *self.key.verifying_key()
+        }
+    }
+
+    impl Authenticator for SoftwareAuthenticator {
+        fn get_assertion(&self, request: &AssertionRequest) -> Result<Assertion> {
+            if !request.credential_ids.iter().any(|id| id == CREDENTIAL_ID) {
+                return Err(eyre!("Unknown credential"));
+            }
+
+            // rpIdHash, flags (user present and verified), signature counter
+            let mut authenticator_data = Sha256::digest(request.rp_id.as_bytes()).to_vec();
+            authenticator_data.push(0b0000_0101);
+            authenticator_data.extend_from_slice(&1u32.to_be_bytes());
+
+            let signed = [
+                authenticator_data.as_slice(),
+                request.client_data_hash.as_slice(),
+            ]
+            .concat();
+            let signature: Signature = self.key.sign(&signed);
+
+            Ok(Assertion {
+                authenticator_data,
+                signature: signature.to_der().as_bytes().to_vec(),
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn verifies_with_software_authenticator() -> Result<()> {
+        let server = MockServer::start().await;
+        let verify_url =
+            Url::parse(&server.uri())?.join("api/v1/authn/factors/FACTOR_ID/verify")?;
+
+        Mock::given(method("POST"))
+            .and(path("/api/v1/authn/factors/FACTOR_ID/verify"))
+            .and(body_partial_json(json!({ "stateToken": "STATE_TOKEN" })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "stateToken": "CHALLENGE_STATE_TOKEN",
+                "status": "MFA_CHALLENGE",
+                "factorResult": "CHALLENGE",
+                "_embedded": {
+                    "factor": {
+                        "id": "FACTOR_ID",
+                        "factorType": "webauthn",
+                        "provider": "FIDO",
+                        "profile": {
+                            "credentialId": b64url.encode(CREDENTIAL_ID),
+                            "authenticatorName": "YubiKey"
+                        },
+                        "_embedded": {
+                            "challenge": {
+                                "challenge": "CHALLENGE",
+                                "extensions": {}
+                            }
+                        }
+                    }
+                }
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        Mock::given(method("POST"))
+            .and(path("/api/v1/authn/factors/FACTOR_ID/verify"))
+            .and(body_partial_json(
+                json!({ "stateToken": "CHALLENGE_STATE_TOKEN" }),
+            ))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "status": "SUCCESS",
+                "sessionToken": "SESSION_TOKEN"
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let authenticator = SoftwareAuthenticator::new();
+        let verifying_key = authenticator.verifying_key();
+
+        let mut client = Client::unauthenticated(Url::parse(&server.uri())?, "user")?;
+        client.set_webauthn_authenticator(authenticator);
+
+        let response = client
+            .verify_webauthn(verify_url, "STATE_TOKEN".to_string())
+            .await?;
+        assert_eq!(response.session_token, Some("SESSION_TOKEN".to_string()));
+
+        // Check the assertion that was sent to Okta
+        let requests = server.received_requests().await.unwrap();
+        let body: Value = serde_json::from_slice(&requests[1].body)?;
+
+        let client_data = b64.decode(body["clientData"].as_str().unwrap())?;
+        let client_data: Value = serde_json::from_slice(&client_data)?;
+        assert_eq!(client_data["type"], "webauthn.get");
+        assert_eq!(client_data["challenge"], "CHALLENGE");
+        assert_eq!(client_data["origin"], server.uri());
+
+        let authenticator_data = b64.decode(body["authenticatorData"].as_str().unwrap())?;
+        let signature = b64.decode(body["signatureData"].as_str().unwrap())?;
+        let client_data_hash = Sha256::digest(b64.decode(body["clientData"].as_str().unwrap())?);
+        verifying_key.verify(
+            &[authenticator_data.as_slice(), client_data_hash.as_slice()].concat(),
+            &Signature::from_der(&signature)?,
+        )?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn requires_an_authenticator() -> Result<()> {
+        let client = Client::unauthenticated(Url::parse("https://example.okta.com/")?, "user")?;
+
+        let result = client
+            .verify_webauthn(
+                Url::parse("https://example.okta.com/api/v1/authn/factors/FACTOR_ID/verify")?,
+                "STATE_TOKEN".to_string(),
+            )
+            .await;
+
+        assert!(result.is_err());
+
+        Ok(())
+    }
+}
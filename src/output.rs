@@ -0,0 +1,490 @@
+use std::fmt;
+use std::io::Write;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use aws_credential_types::Credentials;
+use aws_smithy_types::DateTime;
+use aws_smithy_types::date_time::Format;
+use eyre::{Error, Result, eyre};
+use serde::Serialize;
+
+/// How commands report what they did
+///
+/// In `Text` mode, progress and results are written for people, on stdout.
+/// In `Json` mode, each [`Event`] is written to stdout as a line of JSON,
+/// and progress for people is written to stderr instead.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Output {
+    #[default]
+    Text,
+    Json,
+}
+
+impl FromStr for Output {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match &*s.to_lowercase() {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            _ => Err(eyre!("Unknown output format {s} (expected text or json)")),
+        }
+    }
+}
+
+impl fmt::Display for Output {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Text => write!(f, "text"),
+            Self::Json => write!(f, "json"),
+        }
+    }
+}
+
+impl Output {
+    /// Write a line of progress for people
+    pub fn message(self, message: &str) {
+        match self {
+            Self::Text => println!("{message}"),
+            Self::Json => eprintln!("{message}"),
+        }
+    }
+
+    /// Write progress for people, leaving the line open for more
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the output cannot be flushed
+    pub fn progress(self, message: &str) -> Result<()> {
+        match self {
+            Self::Text => {
+                print!("{message}");
+                std::io::stdout().flush()?;
+            }
+            Self::Json => {
+                eprint!("{message}");
+                std::io::stderr().flush()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Write an event for scripts (only in `Json` mode)
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the event cannot be serialized
+    pub fn event(self, event: &Event) -> Result<()> {
+        if self == Self::Json {
+            println!("{}", serde_json::to_string(event)?);
+        }
+        Ok(())
+    }
+}
+
+/// The exit code of `refresh` when some profiles failed, but others were refreshed
+pub const PARTIAL_FAILURE: u8 = 2;
+
+/// The exit code of `refresh` when every profile it tried to refresh failed
+pub const TOTAL_FAILURE: u8 = 3;
+
+/// Why a profile was not refreshed
+#[derive(Clone, Copy, Debug, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SkipReason {
+    /// The profile already exists as an SSO profile for another application in `~/.aws/config`
+    SsoConflict,
+    /// The cached credentials for the profile are still valid
+    Fresh,
+    /// `--fail-fast` stopped the refresh before the profile was refreshed
+    FailFast,
+}
+
+impl fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::SsoConflict => write!(f, "already an SSO profile in ~/.aws/config"),
+            Self::Fresh => write!(f, "cached credentials are still valid"),
+            Self::FailFast => write!(f, "not refreshed after an earlier failure"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, PartialEq, Eq)]
+pub struct SkippedProfile {
+    pub profile: String,
+    pub reason: SkipReason,
+}
+
+#[derive(Clone, Debug, Serialize, PartialEq, Eq)]
+pub struct FailedProfile {
+    pub profile: String,
+    pub error: String,
+}
+
+/// What `refresh` did to each profile, reported once it has finished
+#[derive(Clone, Debug, Default, Serialize, PartialEq, Eq)]
+pub struct RefreshSummary {
+    pub refreshed: Vec<String>,
+    pub skipped: Vec<SkippedProfile>,
+    pub failed: Vec<FailedProfile>,
+}
+
+impl RefreshSummary {
+    /// Add a profile event to the summary, and write it out
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the event cannot be serialized
+    pub fn report(&mut self, output: Output, event: &Event) -> Result<()> {
+        match event {
+            Event::ProfileRefreshed { profile, .. } => self.refreshed.push(profile.clone()),
+            Event::ProfileSkipped {
+                profile, reason, ..
+            } => self.skipped.push(SkippedProfile {
+                profile: profile.clone(),
+                reason: *reason,
+            }),
+            Event::ProfileFailed { profile, error, .. } => self.failed.push(FailedProfile {
+                profile: profile.clone(),
+                error: error.clone(),
+            }),
+            _ => {}
+        }
+
+        output.event(event)
+    }
+
+    /// `0` if nothing failed, [`PARTIAL_FAILURE`] if only some profiles failed,
+    /// or [`TOTAL_FAILURE`] if no profiles could be refreshed
+    #[must_use]
+    pub const fn exit_code(&self) -> u8 {
+        if self.failed.is_empty() {
+            0
+        } else if self.refreshed.is_empty() {
+            TOTAL_FAILURE
+        } else {
+            PARTIAL_FAILURE
+        }
+    }
+}
+
+impl fmt::Display for RefreshSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Refreshed {}, skipped {}, failed {}",
+            self.refreshed.len(),
+            self.skipped.len(),
+            self.failed.len()
+        )?;
+        for SkippedProfile { profile, reason } in &self.skipped {
+            write!(f, "\n  - {profile}: skipped ({reason})")?;
+        }
+        for FailedProfile { profile, error } in &self.failed {
+            write!(f, "\n  ✗ {profile}: {error}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Something a command did, as written in `Json` mode.
+///
+/// These are serialized with an `event` field naming the variant, and are part of the
+/// interface that scripts rely on, so fields should only ever be added.
+#[derive(Clone, Debug, Serialize, PartialEq, Eq)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    /// Credentials for a profile were fetched and written to `~/.aws/credentials`
+    ProfileRefreshed {
+        organization: String,
+        profile: String,
+        /// RFC 3339 timestamp, in UTC
+        expiration: Option<String>,
+    },
+    /// A profile was not refreshed
+    ProfileSkipped {
+        organization: String,
+        profile: String,
+        reason: SkipReason,
+    },
+    /// Credentials for a profile could not be fetched
+    ProfileFailed {
+        organization: String,
+        profile: String,
+        error: String,
+    },
+    /// `init` generated a configuration for an organization
+    OrganizationConfigured {
+        organization: String,
+        path: PathBuf,
+        profiles: Vec<String>,
+        written: bool,
+    },
+    /// `init-sso` found an SSO application
+    SsoSessionFound {
+        session: String,
+        display_name: String,
+        start_url: String,
+        region: String,
+        accounts: usize,
+    },
+    /// `init-sso` found nothing to configure for an SSO application
+    SsoSessionSkipped { session: String, reason: String },
+    /// `init-sso` configured a profile in `~/.aws/config`
+    SsoProfileConfigured {
+        session: String,
+        profile: String,
+        account_id: String,
+        role: String,
+    },
+    /// `init-sso` finished configuring profiles
+    SsoConfigured { profiles: usize, written: bool },
+    /// `refresh` finished, with what happened to each profile
+    RefreshFinished(RefreshSummary),
+}
+
+impl Event {
+    /// # Errors
+    ///
+    /// Will return `Err` if the expiry cannot be formatted as a timestamp
+    pub fn profile_refreshed(
+        organization: &str,
+        profile: &str,
+        credentials: &Credentials,
+    ) -> Result<Self> {
+        Ok(Self::ProfileRefreshed {
+            organization: organization.to_string(),
+            profile: profile.to_string(),
+            expiration: credentials
+                .expiry()
+                .map(|expiry| DateTime::from(expiry).fmt(Format::DateTime))
+                .transpose()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use serde_json::json;
+
+    #[test]
+    fn parses_formats() -> Result<()> {
+        assert_eq!("json".parse::<Output>()?, Output::Json);
+        assert_eq!("TEXT".parse::<Output>()?, Output::Text);
+        assert!("yaml".parse::<Output>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn refresh_event_schemas() -> Result<()> {
+        let credentials = Credentials::new(
+            "ACCESS_KEY",
+            "SECRET_ACCESS_KEY",
+            None,
+            Some(UNIX_EPOCH + Duration::from_secs(1_700_000_000)),
+            "oktaws",
+        );
+
+        assert_eq!(
+            serde_json::to_value(Event::profile_refreshed("okta", "prod", &credentials)?)?,
+            json!({
+                "event": "profile_refreshed",
+                "organization": "okta",
+                "profile": "prod",
+                "expiration": "2023-11-14T22:13:20Z"
+            })
+        );
+        assert_eq!(
+            serde_json::to_value(Event::ProfileSkipped {
+                organization: "okta".to_string(),
+                profile: "prod".to_string(),
+                reason: SkipReason::SsoConflict,
+            })?,
+            json!({
+                "event": "profile_skipped",
+                "organization": "okta",
+                "profile": "prod",
+                "reason": "sso_conflict"
+            })
+        );
+        assert_eq!(
+            serde_json::to_value(Event::ProfileFailed {
+                organization: "okta".to_string(),
+                profile: "prod".to_string(),
+                error: "Access denied".to_string(),
+            })?,
+            json!({
+                "event": "profile_failed",
+                "organization": "okta",
+                "profile": "prod",
+                "error": "Access denied"
+            })
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn summarizes_refresh() -> Result<()> {
+        let mut summary = RefreshSummary::default();
+        assert_eq!(summary.exit_code(), 0);
+
+        summary.report(
+            Output::Text,
+            &Event::ProfileSkipped {
+                organization: "okta".to_string(),
+                profile: "staging".to_string(),
+                reason: SkipReason::Fresh,
+            },
+        )?;
+        summary.report(
+            Output::Text,
+            &Event::ProfileFailed {
+                organization: "okta".to_string(),
+                profile: "prod".to_string(),
+                error: "Access denied".to_string(),
+            },
+        )?;
+        assert_eq!(summary.exit_code(), TOTAL_FAILURE);
+
+        summary.report(
+            Output::Text,
+            &Event::ProfileRefreshed {
+                organization: "okta".to_string(),
+                profile: "dev".to_string(),
+                expiration: None,
+            },
+        )?;
+        assert_eq!(summary.exit_code(), PARTIAL_FAILURE);
+
+        assert_eq!(
+            summary.to_string(),
+            "Refreshed 1, skipped 1, failed 1\n  - staging: skipped (cached credentials are still valid)\n  ✗ prod: Access denied"
+        );
+        assert_eq!(
+            serde_json::to_value(Event::RefreshFinished(summary))?,
+            json!({
+                "event": "refresh_finished",
+                "refreshed": ["dev"],
+                "skipped": [{"profile": "staging", "reason": "fresh"}],
+                "failed": [{"profile": "prod", "error": "Access denied"}]
+            })
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn summarizes_profiles_abandoned_by_fail_fast() -> Result<()> {
+        let mut summary = RefreshSummary::default();
+        summary.report(
+            Output::Text,
+            &Event::ProfileFailed {
+                organization: "okta".to_string(),
+                profile: "prod".to_string(),
+                error: "Access denied".to_string(),
+            },
+        )?;
+        summary.report(
+            Output::Text,
+            &Event::ProfileSkipped {
+                organization: "okta".to_string(),
+                profile: "dev".to_string(),
+                reason: SkipReason::FailFast,
+            },
+        )?;
+
+        assert_eq!(summary.exit_code(), TOTAL_FAILURE);
+        assert_eq!(
+            summary.to_string(),
+            "Refreshed 0, skipped 1, failed 1\n  - dev: skipped (not refreshed after an earlier failure)\n  ✗ prod: Access denied"
+        );
+        assert_eq!(
+            serde_json::to_value(&summary.skipped)?,
+            json!([{"profile": "dev", "reason": "fail_fast"}])
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn init_event_schemas() -> Result<()> {
+        assert_eq!(
+            serde_json::to_value(Event::OrganizationConfigured {
+                organization: "okta".to_string(),
+                path: PathBuf::from("/home/user/.oktaws/okta.toml"),
+                profiles: vec!["prod".to_string()],
+                written: true,
+            })?,
+            json!({
+                "event": "organization_configured",
+                "organization": "okta",
+                "path": "/home/user/.oktaws/okta.toml",
+                "profiles": ["prod"],
+                "written": true
+            })
+        );
+        assert_eq!(
+            serde_json::to_value(Event::SsoSessionFound {
+                session: "my-sso".to_string(),
+                display_name: "My SSO".to_string(),
+                start_url: "https://d-1234567890.awsapps.com/start".to_string(),
+                region: "us-east-1".to_string(),
+                accounts: 2,
+            })?,
+            json!({
+                "event": "sso_session_found",
+                "session": "my-sso",
+                "display_name": "My SSO",
+                "start_url": "https://d-1234567890.awsapps.com/start",
+                "region": "us-east-1",
+                "accounts": 2
+            })
+        );
+        assert_eq!(
+            serde_json::to_value(Event::SsoSessionSkipped {
+                session: "my-sso".to_string(),
+                reason: "No accounts found".to_string(),
+            })?,
+            json!({
+                "event": "sso_session_skipped",
+                "session": "my-sso",
+                "reason": "No accounts found"
+            })
+        );
+        assert_eq!(
+            serde_json::to_value(Event::SsoProfileConfigured {
+                session: "my-sso".to_string(),
+                profile: "prod".to_string(),
+                account_id: "123456789012".to_string(),
+                role: "AdminAccess".to_string(),
+            })?,
+            json!({
+                "event": "sso_profile_configured",
+                "session": "my-sso",
+                "profile": "prod",
+                "account_id": "123456789012",
+                "role": "AdminAccess"
+            })
+        );
+        assert_eq!(
+            serde_json::to_value(Event::SsoConfigured {
+                profiles: 3,
+                written: false,
+            })?,
+            json!({
+                "event": "sso_configured",
+                "profiles": 3,
+                "written": false
+            })
+        );
+
+        Ok(())
+    }
+}